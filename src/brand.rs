@@ -0,0 +1,60 @@
+use std::cell::{ Cell };
+use std::marker::PhantomData;
+
+use world::{ World };
+
+/// WorldId of Worlds created by scope
+/// Invariant in 'id, so every scope gets its own incompatible WorldId
+/// while all scoped Worlds share one monomorphization of the managers
+pub struct Brand<'id> {
+    phantom: PhantomData<Cell<&'id mut ()>>,
+}
+
+/// Run f with a new World, branded with a lifetime unique to this call
+/// Entities can't escape f, and using them with a World from another scope fails to compile
+/// Operations that move values between Worlds require a 'static WorldId and aren't available:
+/// move_entity_to, merge and register_map_entities
+///
+/// ```compile_fail
+/// use ecs::scope;
+///
+/// scope(|mut world1| {
+///     let entity1 = world1.create_entity();
+///
+///     scope(|world2| {
+///         world2.is_valid(&entity1)
+///     })
+/// });
+/// ```
+pub fn scope<F, R>(f: F) -> R where F: for<'id> FnOnce(World<Brand<'id>>) -> R {
+    f(World::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{ VecMap };
+
+    use super::{ scope };
+
+    #[test]
+    fn scoped_worlds() {
+        struct Cmp1(isize);
+
+        let components = scope(|mut world1| {
+            world1.register_component::<Cmp1>(Box::new(VecMap::new()));
+            let entity1 = world1.create_entity();
+            world1.assign_component(&entity1, Cmp1(1));
+
+            scope(|mut world2| {
+                world2.register_component::<Cmp1>(Box::new(VecMap::new()));
+                let entity2 = world2.create_entity();
+                world2.assign_component(&entity2, Cmp1(2));
+
+                // world1.get_component::<Cmp1>(&entity2) doesn't compile
+                world1.get_component::<Cmp1>(&entity1).unwrap().0 + world2.get_component::<Cmp1>(&entity2).unwrap().0
+            })
+        });
+
+        assert_eq!(components, 3);
+    }
+}
@@ -0,0 +1,54 @@
+// Little endian encoding helpers for packets and save files
+
+// return None from the enclosing function if there are too few bytes
+macro_rules! try_read {
+    ($e:expr) => (match $e { Some(x) => x, None => return None })
+}
+
+pub fn write_u32(buffer: &mut Vec<u8>, value: u32) {
+    for i in 0..4 {
+        buffer.push((value >> (i * 8)) as u8);
+    }
+}
+
+pub fn write_u64(buffer: &mut Vec<u8>, value: u64) {
+    write_u32(buffer, value as u32);
+    write_u32(buffer, (value >> 32) as u32);
+}
+
+pub fn read_u32(buffer: &mut &[u8]) -> Option<u32> {
+    if buffer.len() < 4 {
+        return None;
+    }
+
+    let value = (0..4).fold(0u32, |value, i| value | ((buffer[i] as u32) << (i * 8)));
+    *buffer = &buffer[4..];
+    Some(value)
+}
+
+pub fn read_u64(buffer: &mut &[u8]) -> Option<u64> {
+    let low = try_read!(read_u32(buffer)) as u64;
+    let high = try_read!(read_u32(buffer)) as u64;
+    Some(low | (high << 32))
+}
+
+pub fn read_bytes<'a>(buffer: &mut &'a [u8], length: usize) -> Option<&'a [u8]> {
+    if buffer.len() < length {
+        return None;
+    }
+
+    let bytes = &buffer[..length];
+    *buffer = &buffer[length..];
+    Some(bytes)
+}
+
+pub fn write_string(buffer: &mut Vec<u8>, value: &str) {
+    write_u32(buffer, value.len() as u32);
+    buffer.push_all(value.as_bytes());
+}
+
+pub fn read_string(buffer: &mut &[u8]) -> Option<String> {
+    let length = try_read!(read_u32(buffer)) as usize;
+    let bytes = try_read!(read_bytes(buffer, length));
+    String::from_utf8(bytes.to_vec()).ok()
+}
@@ -1,27 +1,93 @@
+use std::any::{ Any, TypeId };
+use std::cell::{ RefCell };
+use std::cmp::{ self, Ordering };
 use std::marker::PhantomData;
 use std::collections::{ BitVec, VecMap, HashMap };
+use std::fmt::{ Debug, Formatter, Error };
+use std::hash::{ Hash, SipHasher };
+use std::intrinsics;
+use std::iter::{ Enumerate };
+use std::mem;
+use std::slice::{ Iter };
+use std::vec::{ IntoIter };
 
 use anymap::AnyMap;
+use bincode::{ self, SizeLimit };
+use rustc_serialize::{ Encodable, Decodable };
+use rustc_serialize::json::{ self, Json };
 
-use entity::{ Entity };
+use entity::{ EntityManager, Entity, EntityIterator, EntityMap, MapEntities };
+use query::{ Filter, Fetch, FilterIterator, ParFetch };
+use thread_pool::{ ThreadPool };
+use index::{ ComponentIndex, ValueIndex, Spatial, SpatialGrid };
 
-// TODO more DB like approach to ECS i.e. more powerful query tools
 // TODO Add Component Copy-on-Write from Template
 // TODO Consider using unsafe for transmuting Option
 // use std::mem::transmute;
 
 pub struct ComponentData<Component: 'static> {
     pub index: usize,
-    pub list: Box<ComponentList<Component> + 'static>
+    pub list: Box<ComponentList<Component> + 'static>,
+    // ticks at which the component was last added and last changed, by entity index
+    added_ticks: VecMap<usize>,
+    changed_ticks: VecMap<usize>,
+    // entity index, entity version, tick and value of removed components, see clear_removed_until
+    removed: Vec<(usize, usize, usize, Option<Component>)>,
+    // schema version a migration reads and the migration to the current version
+    migrations: Vec<(u32, Box<Fn(&[u8]) -> Option<Component> + 'static>)>,
+    // secondary indexes and the entity indices changed through get_component_mut since they were updated
+    // lookups only borrow the ComponentManager, so they apply the changes through the RefCells
+    indexes: RefCell<Vec<Box<ComponentIndex<Component> + 'static>>>,
+    dirty_indexes: RefCell<Vec<usize>>,
+    // by entity index, whether it's in dirty_indexes, so changing a component every frame doesn't grow it
+    dirty_entities: RefCell<BitVec>,
+}
+
+impl<Component: 'static> ComponentData<Component> {
+    fn update_indexes(&self, entity_index: usize) {
+        for index in self.indexes.borrow_mut().iter_mut() {
+            index.remove(entity_index);
+            if let Some(component) = self.list.get(&entity_index) {
+                index.insert(entity_index, component);
+            }
+        }
+    }
+
+    fn mark_dirty(&self, entity_index: usize) {
+        let mut dirty_entities = self.dirty_entities.borrow_mut();
+        if entity_index >= dirty_entities.len() {
+            let grow_by = entity_index + 1 - dirty_entities.len();
+            dirty_entities.grow(grow_by, false);
+        }
+
+        if !dirty_entities.get(entity_index).unwrap() {
+            dirty_entities.set(entity_index, true);
+            self.dirty_indexes.borrow_mut().push(entity_index);
+        }
+    }
+
+    fn update_dirty_indexes(&self) {
+        let dirty_indexes = mem::replace(&mut *self.dirty_indexes.borrow_mut(), Vec::new());
+        for entity_index in dirty_indexes.into_iter() {
+            self.dirty_entities.borrow_mut().set(entity_index, false);
+            self.update_indexes(entity_index);
+        }
+    }
 }
 
 // TODO Add BTreeMap
 pub trait ComponentList<Component> {
     fn contains_key(&self, &usize) -> bool;
     fn get(&self, &usize) -> Option<&Component>;
+    // par_for_each holds the components of several entities at once, so getting one must not move the others
     fn get_mut(&mut self, &usize) -> Option<&mut Component>;
     fn insert(&mut self, usize, Component);
     fn remove(&mut self, key: &usize) -> Option<Component>;
+    fn storage_kind(&self) -> StorageKind { StorageKind::Other }
+    /// Physically reorder the components, false if the storage has no order
+    fn sort_by(&mut self, _: &Fn(&Component, &Component) -> Ordering) -> bool { false }
+    /// Entity indices in storage order, None if the storage has no order
+    fn ordered_indices(&self) -> Option<Vec<usize>> { None }
     // fn iter(&self) -> Box<Iterator<Item=(usize, &Component)>>;
     // fn iter_mut(&mut self) -> Box<Iterator<Item=(usize, &mut Component)>>;
 }
@@ -32,6 +98,7 @@ impl<Component> ComponentList<Component> for VecMap<Component> {
     fn get_mut(&mut self, index: &usize) -> Option<&mut Component> { self.get_mut(index) }
     fn insert(&mut self, index: usize, component: Component) { self.insert(index, component); }
     fn remove(&mut self, key: &usize) -> Option<Component> { self.remove(key) }
+    fn storage_kind(&self) -> StorageKind { StorageKind::VecMap }
     // fn iter(&self) -> Box<Iterator<Item=(usize, &Component)>> { Box::new(self.iter()) }
     // fn iter_mut(&mut self) -> Box<Iterator<Item=(usize, &mut Component)>> { Box::new(self.iter_mut()) }
 }
@@ -42,15 +109,449 @@ impl<Component> ComponentList<Component> for HashMap<usize, Component> {
     fn get_mut(&mut self, index: &usize) -> Option<&mut Component> { self.get_mut(index) }
     fn insert(&mut self, index: usize, component: Component) { self.insert(index, component); }
     fn remove(&mut self, key: &usize) -> Option<Component> { self.remove(key) }
+    fn storage_kind(&self) -> StorageKind { StorageKind::HashMap }
     // fn iter(&self) -> Box<Iterator<Item=(usize, &Component)>> { Box::new(self.iter().map(|(index, component)| (*index, component))) }
     // fn iter_mut(&mut self) -> Box<Iterator<Item=(usize, &mut Component)>> { Box::new(self.iter_mut().map(|(index, component)| (*index, component))) }
 }
 
+/// Components stored contiguously, which can be sorted so iterating in that order is cache friendly
+/// Adding and removing components changes the order until the next sort
+pub struct DenseList<Component> {
+    // entity index and component
+    components: Vec<(usize, Component)>,
+    // position of each entity index in components
+    positions: VecMap<usize>,
+}
+
+impl<Component> DenseList<Component> {
+    pub fn new() -> DenseList<Component> {
+        DenseList {
+            components: Vec::new(),
+            positions: VecMap::new(),
+        }
+    }
+}
+
+impl<Component> ComponentList<Component> for DenseList<Component> {
+    fn contains_key(&self, index: &usize) -> bool { self.positions.contains_key(index) }
+
+    fn get(&self, index: &usize) -> Option<&Component> {
+        self.positions.get(index).map(|&position| &self.components[position].1)
+    }
+
+    fn get_mut(&mut self, index: &usize) -> Option<&mut Component> {
+        match self.positions.get(index).map(|&position| position) {
+            Some(position) => Some(&mut self.components[position].1),
+            None => None,
+        }
+    }
+
+    fn insert(&mut self, index: usize, component: Component) {
+        match self.positions.get(&index).map(|&position| position) {
+            Some(position) => self.components[position].1 = component,
+            None => {
+                self.positions.insert(index, self.components.len());
+                self.components.push((index, component));
+            },
+        }
+    }
+
+    fn remove(&mut self, key: &usize) -> Option<Component> {
+        let position = match self.positions.remove(key) {
+            Some(position) => position,
+            None => return None,
+        };
+
+        let (_, component) = self.components.swap_remove(position);
+        if position < self.components.len() {
+            self.positions.insert(self.components[position].0, position);
+        }
+        Some(component)
+    }
+
+    fn storage_kind(&self) -> StorageKind { StorageKind::Dense }
+
+    fn sort_by(&mut self, compare: &Fn(&Component, &Component) -> Ordering) -> bool {
+        self.components.sort_by(|&(_, ref a), &(_, ref b)| compare(a, b));
+
+        for (position, &(index, _)) in self.components.iter().enumerate() {
+            self.positions.insert(index, position);
+        }
+        true
+    }
+
+    fn ordered_indices(&self) -> Option<Vec<usize>> {
+        Some(self.components.iter().map(|&(index, _)| index).collect())
+    }
+}
+
+/// Identifies both typed and dynamic components by their index in the entity component masks
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ComponentId(pub usize);
+
+// Components without a Rust type, for scripting and editors
+struct DynamicComponentData {
+    name: String,
+    list: VecMap<Box<Any>>,
+    removed: Vec<(usize, usize, usize, Option<Box<Any>>)>,
+}
+
+/// Identifies a query registered with register_query
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct QueryId(pub usize);
+
+// Entity indices matching a query, updated whenever an entity component mask changes
+struct QueryCache {
+    // component indices, sorted
+    with: Vec<usize>,
+    without: Vec<usize>,
+    entity_indices: Vec<usize>,
+    // position of each entity index in entity_indices
+    positions: VecMap<usize>,
+}
+
+impl QueryCache {
+    fn matches(&self, entity_component_mask: &BitVec) -> bool {
+        self.with.iter().all(|&index| entity_component_mask.get(index).unwrap_or(false))
+        && !self.without.iter().any(|&index| entity_component_mask.get(index).unwrap_or(false))
+    }
+
+    fn update(&mut self, entity_index: usize, entity_component_mask: &BitVec) {
+        match (self.matches(entity_component_mask), self.positions.contains_key(&entity_index)) {
+            (true, false) => {
+                self.positions.insert(entity_index, self.entity_indices.len());
+                self.entity_indices.push(entity_index);
+            },
+            (false, true) => {
+                let position = self.positions.remove(&entity_index).unwrap();
+                self.entity_indices.swap_remove(position);
+                if position < self.entity_indices.len() {
+                    self.positions.insert(self.entity_indices[position], position);
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum StorageKind {
+    VecMap,
+    HashMap,
+    Dense,
+    Dynamic,
+    Other,
+}
+
+/// Metadata of a registered component, by component index
+pub struct ComponentInfo<WorldId> {
+    pub name: String,
+    // None for dynamic components
+    pub type_id: Option<TypeId>,
+    pub storage: StorageKind,
+    // version of the encoded component, for saved data written by older versions
+    pub schema_version: u32,
+    // identifies the component in saved data, see register_serialize
+    pub serialize_name: Option<String>,
+
+    // Type erased operations, for when only the component index is known
+    remove: fn(&mut ComponentManager<WorldId>, usize, &Entity<WorldId>),
+    clear_removed: fn(&mut ComponentManager<WorldId>, usize, usize),
+    debug: Option<fn(&ComponentManager<WorldId>, &Entity<WorldId>, &mut Formatter) -> Result<(), Error>>,
+    clone: Option<fn(&mut ComponentManager<WorldId>, &Entity<WorldId>, &Entity<WorldId>)>,
+    take: fn(&mut ComponentManager<WorldId>, usize, &Entity<WorldId>) -> Option<Box<Any>>,
+    insert: fn(&mut ComponentManager<WorldId>, usize, &Entity<WorldId>, Box<Any>),
+    // MapEntitiesFn for each World the component can be moved to
+    map_entities: Vec<Box<Any>>,
+    snapshot: Option<fn(&ComponentManager<WorldId>) -> Box<Any>>,
+    restore: Option<fn(&mut ComponentManager<WorldId>, &Any)>,
+    hash: Option<fn(&ComponentManager<WorldId>, &Entity<WorldId>, &mut SipHasher)>,
+    is_changed_since: fn(&ComponentManager<WorldId>, &Entity<WorldId>, usize) -> bool,
+    encode: Option<fn(&ComponentManager<WorldId>, &Entity<WorldId>) -> Vec<u8>>,
+    decode: Option<fn(&mut ComponentManager<WorldId>, &Entity<WorldId>, &[u8]) -> bool>,
+    can_decode: Option<fn(&[u8]) -> bool>,
+    to_json: Option<fn(&ComponentManager<WorldId>, &Entity<WorldId>) -> Json>,
+    from_json: Option<fn(&mut ComponentManager<WorldId>, &Entity<WorldId>, Json) -> bool>,
+    migrate: Option<fn(&mut ComponentManager<WorldId>, &Entity<WorldId>, u32, &[u8]) -> bool>,
+    on_add: Option<fn(&mut ComponentManager<WorldId>, &Entity<WorldId>)>,
+    on_replace: Option<fn(&mut ComponentManager<WorldId>, &Entity<WorldId>, &Any)>,
+    on_remove: Option<fn(&mut ComponentManager<WorldId>, &Entity<WorldId>)>,
+    // component indices this component requires, with a function inserting their default
+    requires: Vec<(usize, fn(&mut ComponentManager<WorldId>, &Entity<WorldId>))>,
+    // component indices requiring this component
+    required_by: Vec<usize>,
+}
+
+struct MapEntitiesFn<From, To> {
+    map_entities: fn(Box<Any>, &EntityMap<From, To>) -> Box<Any>,
+    // of the converted component
+    type_id: TypeId,
+}
+
+impl<WorldId> ComponentInfo<WorldId> {
+    pub fn is_debug(&self) -> bool {
+        self.debug.is_some()
+    }
+
+    pub fn is_cloneable(&self) -> bool {
+        self.clone.is_some()
+    }
+
+    pub fn is_hashable(&self) -> bool {
+        self.hash.is_some()
+    }
+
+    pub fn is_serializable(&self) -> bool {
+        self.encode.is_some()
+    }
+}
+
+/// Callbacks run synchronously by the ComponentManager, see register_hooks
+/// Hooks can change other components of the entity, e.g. to keep a Transform next to every RigidBody
+pub trait ComponentHooks<WorldId>: 'static {
+    /// After the component was added to an entity that didn't have it
+    fn on_add(_: &mut ComponentManager<WorldId>, _: &Entity<WorldId>) {}
+
+    /// After assign_component replaced the component, old is the previous value
+    /// new is a copy of the stored value, since the hook can change the stored one
+    fn on_replace(_: &mut ComponentManager<WorldId>, _: &Entity<WorldId>, _old: &Self, _new: &Self) {}
+
+    /// Before the component is removed, also when the entity is destroyed
+    fn on_remove(_: &mut ComponentManager<WorldId>, _: &Entity<WorldId>) {}
+}
+
+/// Components assigned together by assign_bundle, implemented for tuples of components
+/// Can't be implemented outside this crate, storing a bundle is only done by assign_bundle
+pub trait Bundle: bundle::StoreBundle {}
+
+// Private module, so store can't be called without setting mask bits and running hooks
+mod bundle {
+    use std::any::{ Any, TypeId };
+
+    use entity::{ Entity };
+    use super::{ ComponentManager };
+
+    pub trait StoreBundle {
+        fn type_ids() -> Vec<TypeId>;
+
+        fn store<WorldId>(self, component_manager: &mut ComponentManager<WorldId>, entity: &Entity<WorldId>, pending: &mut Vec<PendingComponent>);
+    }
+
+    // Component stored by a bundle, whose mask bit, required components and hooks are still pending
+    pub struct PendingComponent {
+        pub index: usize,
+        pub had_component: bool,
+        // only kept for on_replace hooks
+        pub old: Option<Box<Any>>,
+    }
+}
+
+use self::bundle::{ StoreBundle, PendingComponent };
+
+macro_rules! impl_bundle {
+    ($(($T:ident $value:ident)),+) => (
+        impl<$($T: 'static),+> StoreBundle for ($($T,)+) {
+            fn type_ids() -> Vec<TypeId> {
+                vec![$(TypeId::of::<$T>()),+]
+            }
+
+            fn store<WorldId>(self, component_manager: &mut ComponentManager<WorldId>, entity: &Entity<WorldId>, pending: &mut Vec<PendingComponent>) {
+                let ($($value,)+) = self;
+                $(pending.push(component_manager.store_component(entity, $value));)+
+            }
+        }
+
+        impl<$($T: 'static),+> Bundle for ($($T,)+) {}
+    )
+}
+
+// bundles up to 16 components
+for_each_tuple!(impl_bundle, [], (A a), (B b), (C c), (D d), (E e), (F f), (G g), (H h), (I i), (J j), (K k), (L l), (M m), (N n), (O o), (P p));
+
+// Strips the module path, so ecs::Position<ecs::Foo> becomes Position<ecs::Foo>
+fn short_name(name: &str) -> &str {
+    let end = name.find('<').unwrap_or(name.len());
+    match name[..end].rfind("::") {
+        Some(start) => &name[start + 2..],
+        None => name,
+    }
+}
+
+fn remove_component<WorldId, C: 'static>(component_manager: &mut ComponentManager<WorldId>, _: usize, entity: &Entity<WorldId>) {
+    component_manager.remove_component_unchecked::<C>(entity);
+}
+
+fn clear_removed<WorldId, C: 'static>(component_manager: &mut ComponentManager<WorldId>, _: usize, until: usize) {
+    component_manager.get_component_data_mut::<C>().removed.retain(|&(_, _, tick, _)| tick > until);
+}
+
+fn on_add_hook<WorldId, C: ComponentHooks<WorldId>>(component_manager: &mut ComponentManager<WorldId>, entity: &Entity<WorldId>) {
+    C::on_add(component_manager, entity);
+}
+
+fn on_replace_hook<WorldId, C: ComponentHooks<WorldId> + Clone>(component_manager: &mut ComponentManager<WorldId>, entity: &Entity<WorldId>, old: &Any) {
+    // another hook of the same bundle may have removed it already
+    let new = match component_manager.get_component::<C>(entity) {
+        Some(new) => new.clone(),
+        None => return,
+    };
+
+    C::on_replace(component_manager, entity, old.downcast_ref::<C>().unwrap(), &new);
+}
+
+fn on_remove_hook<WorldId, C: ComponentHooks<WorldId>>(component_manager: &mut ComponentManager<WorldId>, entity: &Entity<WorldId>) {
+    C::on_remove(component_manager, entity);
+}
+
+fn insert_default_component<WorldId, C: Default + 'static>(component_manager: &mut ComponentManager<WorldId>, entity: &Entity<WorldId>) {
+    component_manager.assign_component(entity, C::default());
+}
+
+fn debug_component<WorldId, C: Debug + 'static>(component_manager: &ComponentManager<WorldId>, entity: &Entity<WorldId>, formatter: &mut Formatter) -> Result<(), Error> {
+    write!(formatter, "{:?}", component_manager.get_component::<C>(entity).unwrap())
+}
+
+fn clone_component<WorldId, C: Clone + 'static>(component_manager: &mut ComponentManager<WorldId>, source: &Entity<WorldId>, target: &Entity<WorldId>) {
+    let component = component_manager.get_component::<C>(source).unwrap().clone();
+    component_manager.assign_component(target, component);
+}
+
+fn hash_component<WorldId, C: Hash + 'static>(component_manager: &ComponentManager<WorldId>, entity: &Entity<WorldId>, hasher: &mut SipHasher) {
+    component_manager.get_component::<C>(entity).unwrap().hash(hasher);
+}
+
+fn is_changed_since<WorldId, C: 'static>(component_manager: &ComponentManager<WorldId>, entity: &Entity<WorldId>, tick: usize) -> bool {
+    component_manager.is_changed_since::<C>(entity, tick)
+}
+
+fn encode_component<WorldId, C: Encodable + 'static>(component_manager: &ComponentManager<WorldId>, entity: &Entity<WorldId>) -> Vec<u8> {
+    bincode::encode(component_manager.get_component::<C>(entity).unwrap(), SizeLimit::Infinite).unwrap()
+}
+
+fn decode_component<WorldId, C: Decodable + 'static>(component_manager: &mut ComponentManager<WorldId>, entity: &Entity<WorldId>, bytes: &[u8]) -> bool {
+    match bincode::decode::<C>(bytes) {
+        Ok(component) => {
+            component_manager.assign_component(entity, component);
+            true
+        },
+        Err(_) => false,
+    }
+}
+
+fn can_decode_component<C: Decodable>(bytes: &[u8]) -> bool {
+    bincode::decode::<C>(bytes).is_ok()
+}
+
+fn migrate_component<WorldId, C: 'static>(component_manager: &mut ComponentManager<WorldId>, entity: &Entity<WorldId>, version: u32, bytes: &[u8]) -> bool {
+    let component = component_manager.get_component_data::<C>().migrations.iter()
+        .find(|&&(migration_version, _)| migration_version == version)
+        .and_then(|&(_, ref migration)| migration(bytes));
+
+    match component {
+        Some(component) => {
+            component_manager.assign_component(entity, component);
+            true
+        },
+        None => false,
+    }
+}
+
+fn component_to_json<WorldId, C: Encodable + 'static>(component_manager: &ComponentManager<WorldId>, entity: &Entity<WorldId>) -> Json {
+    let encoded = json::encode(component_manager.get_component::<C>(entity).unwrap()).unwrap();
+    Json::from_str(&encoded).unwrap()
+}
+
+fn component_from_json<WorldId, C: Decodable + 'static>(component_manager: &mut ComponentManager<WorldId>, entity: &Entity<WorldId>, json: Json) -> bool {
+    let mut decoder = json::Decoder::new(json);
+    let component: Result<C, _> = Decodable::decode(&mut decoder);
+
+    match component {
+        Ok(component) => {
+            component_manager.assign_component(entity, component);
+            true
+        },
+        Err(_) => false,
+    }
+}
+
+fn snapshot_components<WorldId, C: Clone + 'static>(component_manager: &ComponentManager<WorldId>) -> Box<Any> {
+    let component_data = component_manager.get_component_data::<C>();
+    // in storage order, so restoring keeps the order of sorted storages
+    let components: Vec<(usize, C)> = component_manager.get_ordered_indices::<C>().into_iter()
+        .map(|entity_index| (entity_index, component_data.list.get(&entity_index).unwrap().clone()))
+        .collect();
+
+    Box::new(components)
+}
+
+fn restore_components<WorldId, C: Clone + 'static>(component_manager: &mut ComponentManager<WorldId>, components: &Any) {
+    let components = components.downcast_ref::<Vec<(usize, C)>>().expect("Tried to restore components of wrong type");
+
+    let entity_indices = component_manager.get_entity_indices_with(component_manager.get_component_data::<C>().index);
+    let tick = component_manager.tick;
+
+    let component_data = component_manager.get_component_data_mut::<C>();
+    for entity_index in entity_indices.iter() {
+        component_data.list.remove(entity_index);
+    }
+
+    // restored components count as added, so systems pick up the rolled back state
+    for &(entity_index, ref component) in components.iter() {
+        component_data.list.insert(entity_index, component.clone());
+        component_data.added_ticks.insert(entity_index, tick);
+        component_data.changed_ticks.insert(entity_index, tick);
+    }
+
+    for entity_index in entity_indices.into_iter().chain(components.iter().map(|&(entity_index, _)| entity_index)) {
+        component_data.update_indexes(entity_index);
+    }
+}
+
+fn take_component<WorldId, C: 'static>(component_manager: &mut ComponentManager<WorldId>, _: usize, entity: &Entity<WorldId>) -> Option<Box<Any>> {
+    component_manager.take_component_unchecked::<C>(entity).map(|component| Box::new(component) as Box<Any>)
+}
+
+fn insert_component<WorldId, C: 'static>(component_manager: &mut ComponentManager<WorldId>, _: usize, entity: &Entity<WorldId>, component: Box<Any>) {
+    let component = *component.downcast::<C>().ok().expect("Tried to insert component of wrong type");
+    component_manager.assign_component(entity, component);
+}
+
+fn map_entities<From, To, C: MapEntities<From, To> + 'static>(component: Box<Any>, entity_map: &EntityMap<From, To>) -> Box<Any> {
+    let component = *component.downcast::<C>().ok().expect("Tried to map entities of component of wrong type");
+    Box::new(component.map_entities(entity_map))
+}
+
+fn take_dynamic_component<WorldId>(component_manager: &mut ComponentManager<WorldId>, component_index: usize, entity: &Entity<WorldId>) -> Option<Box<Any>> {
+    component_manager.take_dynamic_component(entity, ComponentId(component_index))
+}
+
+fn insert_dynamic_component<WorldId>(component_manager: &mut ComponentManager<WorldId>, component_index: usize, entity: &Entity<WorldId>, component: Box<Any>) {
+    component_manager.assign_dynamic_component(entity, ComponentId(component_index), component);
+}
+
+fn is_dynamic_component_changed_since<WorldId>(_: &ComponentManager<WorldId>, _: &Entity<WorldId>, _: usize) -> bool {
+    // changes of dynamic components aren't tracked
+    true
+}
+
+fn remove_dynamic_component<WorldId>(component_manager: &mut ComponentManager<WorldId>, component_index: usize, entity: &Entity<WorldId>) {
+    component_manager.remove_dynamic_component(entity, ComponentId(component_index));
+}
+
+fn clear_removed_dynamic<WorldId>(component_manager: &mut ComponentManager<WorldId>, component_index: usize, until: usize) {
+    component_manager.get_dynamic_component_data_mut(ComponentId(component_index)).removed.retain(|&(_, _, tick, _)| tick > until);
+}
+
 pub struct ComponentManager<WorldId> {
     phantom: PhantomData<WorldId>,
     entity_component_masks: Vec<BitVec>,
     next_component_index: usize,
     component_data: AnyMap,
+    // by component index
+    dynamic_component_data: VecMap<DynamicComponentData>,
+    component_infos: Vec<ComponentInfo<WorldId>>,
+    query_caches: Vec<QueryCache>,
+    tick: usize,
 }
 
 impl<'a, WorldId> ComponentManager<WorldId> {
@@ -60,6 +561,11 @@ impl<'a, WorldId> ComponentManager<WorldId> {
             entity_component_masks: Vec::with_capacity(initial_capacity),
             next_component_index: 0,
             component_data: AnyMap::new(),
+            dynamic_component_data: VecMap::new(),
+            component_infos: Vec::new(),
+            query_caches: Vec::new(),
+            // 0 is reserved for "never", so everything is newer than a system that hasn't run yet
+            tick: 1,
         }
     }
 
@@ -73,23 +579,77 @@ impl<'a, WorldId> ComponentManager<WorldId> {
     }
 
     pub fn entity_destroyed(&mut self, entity: &Entity<WorldId>) {
-        self.entity_component_masks[entity.index()].clear();
+        let component_indices: Vec<usize> = self.entity_component_masks[entity.index()].iter()
+            .enumerate()
+            .filter(|&(_, has_component)| has_component)
+            .map(|(component_index, _)| component_index)
+            .collect();
+
+        for component_index in component_indices.into_iter() {
+            let remove = self.component_infos[component_index].remove;
+            remove(self, component_index, entity);
+        }
+    }
+
+    // Allocate the next component index for a new component type
+    fn add_component_index(&mut self, component_info: ComponentInfo<WorldId>) -> usize {
+        let index = self.next_component_index;
+
+        self.component_infos.push(component_info);
+        self.next_component_index += 1;
+
+        for mut entity_component_mask in self.entity_component_masks.iter_mut() {
+            // dynamically grow bitv length, only needed if new component types can be registered after entities have been added
+            entity_component_mask.grow(self.next_component_index, false);
+        }
+
+        index
     }
 
     pub fn register_component<C: 'static>(&mut self, component_list: Box<ComponentList<C> + 'static>) {
         match self.component_data.get::<ComponentData<C>>() {
             None => {
+                let index = self.add_component_index(ComponentInfo {
+                    name: unsafe { intrinsics::type_name::<C>() }.to_string(),
+                    type_id: Some(TypeId::of::<C>()),
+                    storage: component_list.storage_kind(),
+                    schema_version: 0,
+                    serialize_name: None,
+                    remove: remove_component::<WorldId, C>,
+                    clear_removed: clear_removed::<WorldId, C>,
+                    debug: None,
+                    clone: None,
+                    take: take_component::<WorldId, C>,
+                    insert: insert_component::<WorldId, C>,
+                    map_entities: Vec::new(),
+                    snapshot: None,
+                    restore: None,
+                    hash: None,
+                    is_changed_since: is_changed_since::<WorldId, C>,
+                    encode: None,
+                    decode: None,
+                    can_decode: None,
+                    to_json: None,
+                    from_json: None,
+                    migrate: None,
+                    on_add: None,
+                    on_replace: None,
+                    on_remove: None,
+                    requires: Vec::new(),
+                    required_by: Vec::new(),
+                });
+
                 self.component_data.insert::<ComponentData<C>>(ComponentData {
-                    index: self.next_component_index,
+                    index: index,
                     list: component_list,
+                    added_ticks: VecMap::new(),
+                    changed_ticks: VecMap::new(),
+                    removed: Vec::new(),
+                    migrations: Vec::new(),
+                    indexes: RefCell::new(Vec::new()),
+                    dirty_indexes: RefCell::new(Vec::new()),
+                    dirty_entities: RefCell::new(BitVec::new()),
                 });
-
-                self.next_component_index += 1;
-
-                for mut entity_component_mask in self.entity_component_masks.iter_mut() {
-                    // dynamically grow bitv length, only needed if new component types can be registered after entities have been added
-                    entity_component_mask.grow(self.next_component_index, false);
-                }
             },
             Some(_) => panic!("Tried to register component twice"),
         }
@@ -97,23 +657,162 @@ impl<'a, WorldId> ComponentManager<WorldId> {
 
     /// Add or replace component on entity
     pub fn assign_component<C: 'static>(&mut self, entity: &Entity<WorldId>, component: C) {
-        let index = {
+        let pending = self.store_component(entity, component);
+        self.entity_component_masks[entity.index()].set(pending.index, true);
+        self.mask_changed(entity.index());
+        self.finish_component(entity, pending);
+    }
+
+    /// Add or replace all components of the bundle, setting their mask bits at once
+    /// Required components and hooks run after all components are stored
+    /// Panics if the bundle contains a component type more than once
+    pub fn assign_bundle<B: Bundle>(&mut self, entity: &Entity<WorldId>, bundle: B) {
+        let type_ids = B::type_ids();
+        for (i, type_id) in type_ids.iter().enumerate() {
+            if type_ids[..i].contains(type_id) {
+                panic!("Tried to assign bundle with the same component twice");
+            }
+        }
+
+        let mut pending = Vec::new();
+        bundle.store(self, entity, &mut pending);
+
+        {
+            let entity_component_mask = &mut self.entity_component_masks[entity.index()];
+            for pending_component in pending.iter() {
+                entity_component_mask.set(pending_component.index, true);
+            }
+        }
+        self.mask_changed(entity.index());
+
+        for pending_component in pending.into_iter() {
+            self.finish_component(entity, pending_component);
+        }
+    }
+
+    // Store the component and its ticks, without setting the mask bit
+    fn store_component<C: 'static>(&mut self, entity: &Entity<WorldId>, component: C) -> PendingComponent {
+        let had_component = self.has_component::<C>(entity);
+        let tick = self.tick;
+        let on_replace = self.component_infos[self.get_component_data::<C>().index].on_replace;
+
+        let (index, old) = {
             let component_data = self.get_component_data_mut::<C>();
-            component_data.list.insert(entity.index(), component);
-            component_data.index
+
+            // swapped in place, so ordered storages keep their order
+            let old = if had_component && on_replace.is_some() {
+                component_data.list.get_mut(&entity.index()).map(|stored| Box::new(mem::replace(stored, component)) as Box<Any>)
+            } else {
+                component_data.list.insert(entity.index(), component);
+                None
+            };
+
+            component_data.update_indexes(entity.index());
+            if !had_component {
+                component_data.added_ticks.insert(entity.index(), tick);
+            }
+            component_data.changed_ticks.insert(entity.index(), tick);
+            (component_data.index, old)
         };
 
-        self.entity_component_masks[entity.index()].set(index, true);
+        PendingComponent {
+            index: index,
+            had_component: had_component,
+            old: old,
+        }
+    }
+
+    // Insert required components and run hooks of a stored component
+    fn finish_component(&mut self, entity: &Entity<WorldId>, pending: PendingComponent) {
+        if !pending.had_component {
+            self.insert_required(entity, pending.index);
+        }
+
+        let on_add = self.component_infos[pending.index].on_add;
+        let on_replace = self.component_infos[pending.index].on_replace;
+
+        match (on_add, on_replace, pending.old) {
+            (Some(on_add), _, _) if !pending.had_component => on_add(self, entity),
+            (_, Some(on_replace), Some(old)) => on_replace(self, entity, &*old),
+            _ => {},
+        }
+    }
+
+    /// Remove component from entity, keeping the value in removed
+    /// False if the entity doesn't have it or another component of the entity requires it, see register_required
+    pub fn remove_component<C: 'static>(&mut self, entity: &Entity<WorldId>) -> bool {
+        let index = self.get_component_data::<C>().index;
+        if !self.has_component::<C>(entity) || self.is_required(entity, index, &[]) {
+            return false;
+        }
+
+        self.remove_component_unchecked::<C>(entity);
+        true
+    }
+
+    // Removing all components of an entity can ignore required components
+    fn remove_component_unchecked<C: 'static>(&mut self, entity: &Entity<WorldId>) {
+        if !self.has_component::<C>(entity) {
+            return;
+        }
+
+        self.run_on_remove::<C>(entity);
+        if !self.has_component::<C>(entity) {
+            return;
+        }
+
+        let component = self.detach_component::<C>(entity);
+        let tick = self.tick;
+        self.get_component_data_mut::<C>().removed.push((entity.index(), entity.version(), tick, component));
+    }
+
+    /// Remove component from entity and return it
+    /// The removal is still visible through removed, but without the value
+    /// None if the entity doesn't have it or another component of the entity requires it, see register_required
+    pub fn take_component<C: 'static>(&mut self, entity: &Entity<WorldId>) -> Option<C> {
+        let index = self.get_component_data::<C>().index;
+        if self.is_required(entity, index, &[]) {
+            return None;
+        }
+
+        self.take_component_unchecked::<C>(entity)
+    }
+
+    fn take_component_unchecked<C: 'static>(&mut self, entity: &Entity<WorldId>) -> Option<C> {
+        if !self.has_component::<C>(entity) {
+            return None;
+        }
+
+        self.run_on_remove::<C>(entity);
+        if !self.has_component::<C>(entity) {
+            return None;
+        }
+
+        let component = self.detach_component::<C>(entity);
+        let tick = self.tick;
+        self.get_component_data_mut::<C>().removed.push((entity.index(), entity.version(), tick, None));
+        component
     }
 
-    pub fn remove_component<C: 'static>(&mut self, entity: &Entity<WorldId>) {
-        let index = {
+    // the hook may have removed the component itself
+    fn run_on_remove<C: 'static>(&mut self, entity: &Entity<WorldId>) {
+        let on_remove = self.component_infos[self.get_component_data::<C>().index].on_remove;
+        if let Some(on_remove) = on_remove {
+            on_remove(self, entity);
+        }
+    }
+
+    fn detach_component<C: 'static>(&mut self, entity: &Entity<WorldId>) -> Option<C> {
+        let (index, component) = {
             let component_data = self.get_component_data_mut::<C>();
-            component_data.list.remove(&entity.index());
-            component_data.index
+            let component = component_data.list.remove(&entity.index());
+            component_data.update_indexes(entity.index());
+            (component_data.index, component)
         };
 
         self.entity_component_masks[entity.index()].set(index, false);
+        self.mask_changed(entity.index());
+        component
     }
 
     pub fn has_component<C: 'static>(&self, entity: &Entity<WorldId>) -> bool {
@@ -136,16 +835,27 @@ impl<'a, WorldId> ComponentManager<WorldId> {
         }
     }
 
+    /// Marks the component as changed, since there's no way to tell afterwards
     pub fn get_component_mut<C: 'static>(&'a mut self, entity: &Entity<WorldId>) -> Option<&mut C> {
-        // TODO figure out how to do this without cloning
-        let entity_component_mask = self.entity_component_masks[entity.index()].clone();
+        if !self.has_component::<C>(entity) {
+            return None;
+        }
+
+        self.set_changed::<C>(entity);
+        self.get_component_data_mut::<C>().list.get_mut(&entity.index())
+    }
+
+    /// Mark the component as changed like get_component_mut, for changes made without it
+    pub fn set_changed<C: 'static>(&mut self, entity: &Entity<WorldId>) {
+        if !self.has_component::<C>(entity) {
+            return;
+        }
 
+        let tick = self.tick;
         let component_data = self.get_component_data_mut::<C>();
-        let component = component_data.list.get_mut(&entity.index());
-        if component.is_some() && !entity_component_mask.get(component_data.index).unwrap() {
-            None
-        } else {
-            component
+        component_data.changed_ticks.insert(entity.index(), tick);
+        if !component_data.indexes.borrow().is_empty() {
+            component_data.mark_dirty(entity.index());
         }
     }
 
@@ -173,109 +883,1820 @@ impl<'a, WorldId> ComponentManager<WorldId> {
         self.next_component_index
     }
 
-}
-
-#[cfg(test)]
-mod tests {
-    use super::{
-        ComponentManager
-    };
-    use entity::{ EntityManager };
-    use std::collections::{ VecMap, HashMap };
+    pub fn get_component_id<C: 'static>(&self) -> ComponentId {
+        ComponentId(self.get_component_data::<C>().index)
+    }
 
-    #[test]
-    fn register_components() {
-        struct WorldId1;
-        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+    /// Works for both typed and dynamic components
+    /// False if the entity doesn't have it or another component of the entity requires it
+    pub fn remove_component_id(&mut self, entity: &Entity<WorldId>, component_id: ComponentId) -> bool {
+        if !self.has_component_id(entity, component_id) {
+            return false;
+        }
 
-        // test different datastructures
-        #[derive(PartialEq, Debug)]
-        struct UnitComponent;
-        component_manager.register_component::<UnitComponent>(Box::new(VecMap::new()));
+        self.remove_component_ids(entity, &[component_id])
+    }
 
-        #[derive(PartialEq, Debug)]
-        struct TupleComponent(isize);
-        component_manager.register_component::<TupleComponent>(Box::new(HashMap::new()));
+    /// Remove all of the components at once, so components can be removed together with the ones requiring them
+    /// False without removing anything if a component the entity keeps requires one of them
+    pub fn remove_component_ids(&mut self, entity: &Entity<WorldId>, component_ids: &[ComponentId]) -> bool {
+        let indices: Vec<usize> = component_ids.iter().map(|component_id| component_id.0).collect();
+        if indices.iter().any(|&index| self.is_required(entity, index, &indices[..])) {
+            return false;
+        }
 
-        #[derive(PartialEq, Debug)]
-        struct Component {
-            field: isize,
+        for &index in indices.iter() {
+            if self.entity_component_masks[entity.index()].get(index).unwrap_or(false) {
+                let remove = self.component_infos[index].remove;
+                remove(self, index, entity);
+            }
         }
-        component_manager.register_component::<Component>(Box::new(VecMap::new()));
+
+        true
     }
 
-    #[test]
-    fn unassigned_components() {
+    /// Works for both typed and dynamic components
+    pub fn has_component_id(&self, entity: &Entity<WorldId>, component_id: ComponentId) -> bool {
+        self.entity_component_masks[entity.index()].get(component_id.0).unwrap_or(false)
+    }
+
+    // *** Hooks ***
+
+    /// Run the ComponentHooks of the component whenever it's added, replaced or removed
+    /// Dynamic components and restoring snapshots don't run hooks
+    pub fn register_hooks<C: ComponentHooks<WorldId> + Clone>(&mut self) {
+        let index = self.get_component_data::<C>().index;
+        self.component_infos[index].on_add = Some(on_add_hook::<WorldId, C>);
+        self.component_infos[index].on_replace = Some(on_replace_hook::<WorldId, C>);
+        self.component_infos[index].on_remove = Some(on_remove_hook::<WorldId, C>);
+    }
+
+    // *** Required components ***
+
+    /// Assigning C also assigns the default of Required, unless the entity already has it
+    /// Required can't be removed from an entity while it has C, unless both are removed with remove_component_ids
+    /// Entities that already have C but not Required get its default now, so the order of registering and assigning doesn't matter
+    pub fn register_required<C: 'static, Required: Default + 'static>(&mut self, entity_manager: &EntityManager<WorldId>) {
+        let index = self.get_component_data::<C>().index;
+        let required_index = self.get_component_data::<Required>().index;
+
+        if index == required_index || self.requires_component(required_index, index) {
+            panic!("Tried to register circular required components");
+        }
+
+        if self.component_infos[index].requires.iter().any(|&(requires_index, _)| requires_index == required_index) {
+            return;
+        }
+
+        self.component_infos[index].requires.push((required_index, insert_default_component::<WorldId, Required>));
+        self.component_infos[required_index].required_by.push(index);
+
+        let entity_indices: Vec<usize> = self.get_entity_indices_with(index).into_iter()
+            .filter(|&entity_index| !self.entity_component_masks[entity_index].get(required_index).unwrap_or(false))
+            .collect();
+        for entity_index in entity_indices.into_iter() {
+            insert_default_component::<WorldId, Required>(self, &entity_manager.get_entity(entity_index));
+        }
+    }
+
+    // directly or through other required components
+    fn requires_component(&self, index: usize, required_index: usize) -> bool {
+        self.component_infos[index].requires.iter()
+            .any(|&(requires_index, _)| requires_index == required_index || self.requires_component(requires_index, required_index))
+    }
+
+    fn insert_required(&mut self, entity: &Entity<WorldId>, index: usize) {
+        for i in 0..self.component_infos[index].requires.len() {
+            let (required_index, insert_default) = self.component_infos[index].requires[i];
+            if !self.entity_component_masks[entity.index()].get(required_index).unwrap_or(false) {
+                insert_default(self, entity);
+            }
+        }
+    }
+
+    // by a component of the entity that isn't removed along with it
+    fn is_required(&self, entity: &Entity<WorldId>, index: usize, removed_indices: &[usize]) -> bool {
+        let mask = &self.entity_component_masks[entity.index()];
+        self.component_infos[index].required_by.iter()
+            .any(|&dependent| mask.get(dependent).unwrap_or(false) && !removed_indices.contains(&dependent))
+    }
+
+    // *** Queries ***
+
+    /// Cache the entities with all of the with components and none of the without components
+    /// The cache is kept up to date as components are added and removed, so query only costs the matches
+    /// Registering the same query twice returns the same QueryId
+    pub fn register_query(&mut self, with: &[ComponentId], without: &[ComponentId]) -> QueryId {
+        if with.is_empty() {
+            panic!("Tried to register query without with components");
+        }
+
+        let mut with: Vec<usize> = with.iter().map(|component_id| component_id.0).collect();
+        let mut without: Vec<usize> = without.iter().map(|component_id| component_id.0).collect();
+        with.sort();
+        with.dedup();
+        without.sort();
+        without.dedup();
+
+        if let Some(query_index) = self.query_caches.iter().position(|query_cache| query_cache.with == with && query_cache.without == without) {
+            return QueryId(query_index);
+        }
+
+        self.query_caches.push(QueryCache {
+            with: with,
+            without: without,
+            entity_indices: Vec::new(),
+            positions: VecMap::new(),
+        });
+
+        let query_index = self.query_caches.len() - 1;
+        self.rebuild_query_cache(query_index);
+        QueryId(query_index)
+    }
+
+    /// Indices of the entities matching the query, in no particular order
+    pub fn query(&self, query_id: QueryId) -> &[usize] {
+        &self.query_caches[query_id.0].entity_indices[..]
+    }
+
+    /// Entities matching the query, in no particular order
+    pub fn query_entities(&'a self, entity_manager: &'a EntityManager<WorldId>, query_id: QueryId) -> QueryIterator<'a, WorldId> {
+        QueryIterator {
+            entity_manager: entity_manager,
+            entity_indices: self.query(query_id).iter(),
+        }
+    }
+
+    /// Entities matching the filter, with what Q fetches from them
+    /// e.g. filter::<(&Position, Option<&Velocity>), Or<(With<Player>, With<Enemy>)>>
+    pub fn filter<Q, F>(&'a self, entity_manager: &'a EntityManager<WorldId>) -> FilterIterator<'a, WorldId, Q>
+        where WorldId: 'a, Q: Fetch<'a, WorldId>, F: Filter
+    {
+        FilterIterator::new(self, entity_manager, F::filter_expr(self))
+    }
+
+    /// Entities whose component equals value, e.g. find(&entity_manager, &Player(1))
+    /// Walks the component masks of all entity indices and compares the components of those with it set
+    /// register_index and find_by avoid the scan
+    pub fn find<C: PartialEq + 'static>(&'a self, entity_manager: &'a EntityManager<WorldId>, value: &'a C) -> FindIterator<'a, WorldId, C> {
+        FindIterator {
+            entity_manager: entity_manager,
+            component_data: self.get_component_data::<C>(),
+            entity_component_masks: self.entity_component_masks.iter().enumerate(),
+            value: value,
+        }
+    }
+
+    /// Call f with what Q fetches from every entity matching the query, splitting the entities among threads of thread_pool
+    /// Q is &C, Option<&C>, &mut C or a tuple of them, entities it doesn't match are skipped
+    /// Components are fetched on the calling thread, the threads only get the components of their entities
+    /// Components fetched mutably are marked changed for every matching entity, as there's no way to tell afterwards
+    pub fn par_for_each<Q, F>(&'a mut self, entity_manager: &EntityManager<WorldId>, thread_pool: &mut ThreadPool, query_id: QueryId, threads: usize, f: F)
+        where Q: ParFetch<'a, WorldId>, F: Fn(Entity<WorldId>, Q::Item) + Sync
+    {
+        let mut accesses = Vec::new();
+        Q::accesses(self, &mut accesses);
+        for &(index, mutable) in accesses.iter() {
+            if mutable && accesses.iter().filter(|&&(other_index, _)| other_index == index).count() > 1 {
+                panic!("Tried to fetch component mutably more than once");
+            }
+        }
+
+        // entity indices of a query are distinct, so no component is fetched for two entities
+        let entity_indices = self.query(query_id).to_vec();
+        let mut fetched = Vec::with_capacity(entity_indices.len());
+        for entity_index in entity_indices.into_iter() {
+            let entity = entity_manager.get_entity(entity_index);
+            if let Some(ptr) = Q::resolve(self, &entity) {
+                fetched.push((entity.index(), entity.version(), ptr));
+            }
+        }
+
+        let threads = cmp::max(1, threads);
+        let chunk_size = cmp::max(1, (fetched.len() + threads - 1) / threads);
+        let f = &f;
+
+        let mut fetched = fetched.into_iter();
+        let mut jobs = Vec::new();
+        loop {
+            let chunk: Vec<_> = fetched.by_ref().take(chunk_size).collect();
+            if chunk.is_empty() {
+                break;
+            }
+
+            jobs.push(move || {
+                for (entity_index, entity_version, ptr) in chunk.into_iter() {
+                    // the components stay in place until par_for_each returns, since self is borrowed
+                    f(Entity::new(entity_index, entity_version), unsafe { Q::from_ptr(ptr) });
+                }
+            });
+        }
+
+        thread_pool.scoped(jobs);
+    }
+
+    // Every change of an entity component mask goes through here
+    fn mask_changed(&mut self, entity_index: usize) {
+        let entity_component_mask = &self.entity_component_masks[entity_index];
+        for query_cache in self.query_caches.iter_mut() {
+            query_cache.update(entity_index, entity_component_mask);
+        }
+    }
+
+    fn rebuild_query_cache(&mut self, query_index: usize) {
+        let query_cache = &mut self.query_caches[query_index];
+        query_cache.entity_indices.clear();
+        query_cache.positions.clear();
+
+        for (entity_index, entity_component_mask) in self.entity_component_masks.iter().enumerate() {
+            query_cache.update(entity_index, entity_component_mask);
+        }
+    }
+
+    // *** Secondary indexes ***
+
+    /// Keep the index up to date with the values of the component, filling it with the current values
+    pub fn register_component_index<C: 'static>(&mut self, mut index: Box<ComponentIndex<C> + 'static>) {
+        let entity_indices = self.get_entity_indices_with(self.get_component_data::<C>().index);
+
+        let component_data = self.get_component_data::<C>();
+        component_data.update_dirty_indexes();
+        for entity_index in entity_indices.into_iter() {
+            index.insert(entity_index, component_data.list.get(&entity_index).unwrap());
+        }
+        component_data.indexes.borrow_mut().push(index);
+    }
+
+    /// Index the component by the key, for find_by
+    pub fn register_index<C: 'static, K: Hash + Eq + Clone + 'static>(&mut self, key: fn(&C) -> K) {
+        self.register_component_index::<C>(Box::new(ValueIndex::new(key)));
+    }
+
+    /// Entities whose component has the key, e.g. find_by::<Player, _>(&entity_manager, &1)
+    /// Panics if the component wasn't indexed by register_index with the key type
+    pub fn find_by<C: 'static, K: Hash + Eq + Clone + 'static>(&self, entity_manager: &EntityManager<WorldId>, key: &K) -> IntoIter<Entity<WorldId>> {
+        self.find_in_index::<C, ValueIndex<C, K>, _>(entity_manager, |value_index| value_index.get(key).to_vec())
+    }
+
+    /// Index the component by its position, for find_within_radius and find_within_aabb
+    pub fn register_spatial_index<C: Spatial + 'static>(&mut self, cell_size: f32) {
+        self.register_component_index::<C>(Box::new(SpatialGrid::new(cell_size)));
+    }
+
+    /// Panics if the component wasn't indexed by register_spatial_index
+    pub fn find_within_radius<C: Spatial + 'static>(&self, entity_manager: &EntityManager<WorldId>, center: (f32, f32), radius: f32) -> IntoIter<Entity<WorldId>> {
+        self.find_in_index::<C, SpatialGrid<C>, _>(entity_manager, |grid| grid.within_radius(center, radius))
+    }
+
+    /// Panics if the component wasn't indexed by register_spatial_index
+    pub fn find_within_aabb<C: Spatial + 'static>(&self, entity_manager: &EntityManager<WorldId>, min: (f32, f32), max: (f32, f32)) -> IntoIter<Entity<WorldId>> {
+        self.find_in_index::<C, SpatialGrid<C>, _>(entity_manager, |grid| grid.within_aabb(min, max))
+    }
+
+    // Look up entity indices in the first index of type I of the component
+    fn find_in_index<C: 'static, I: 'static, F>(&self, entity_manager: &EntityManager<WorldId>, find: F) -> IntoIter<Entity<WorldId>>
+        where F: Fn(&I) -> Vec<usize>
+    {
+        let component_data = self.get_component_data::<C>();
+        component_data.update_dirty_indexes();
+
+        let indexes = component_data.indexes.borrow();
+        let index = indexes.iter()
+            .filter_map(|index| index.as_any().downcast_ref::<I>())
+            .next()
+            .expect("Tried to find by component without index");
+
+        let entities: Vec<Entity<WorldId>> = find(index).into_iter()
+            .map(|entity_index| entity_manager.get_entity(entity_index))
+            .collect();
+        entities.into_iter()
+    }
+
+    /// Apply changes through get_component_mut to the indexes of the component
+    /// Lookups do this themselves, custom indexes can call it before reading
+    pub fn update_indexes<C: 'static>(&self) {
+        self.get_component_data::<C>().update_dirty_indexes();
+    }
+
+    // *** Sorting ***
+
+    /// Physically reorder the components, so entities_in_order and later sorts follow that order
+    /// Returns false and leaves the storage alone if it has no order, only DenseList can be sorted
+    pub fn sort_components<C: 'static, F>(&mut self, compare: F) -> bool where F: Fn(&C, &C) -> Ordering {
+        self.get_component_data_mut::<C>().list.sort_by(&compare)
+    }
+
+    /// Entities with the component in storage order, entity index order for storages without order
+    pub fn entities_in_order<C: 'static>(&self, entity_manager: &EntityManager<WorldId>) -> IntoIter<Entity<WorldId>> {
+        let entities: Vec<Entity<WorldId>> = self.get_ordered_indices::<C>().into_iter()
+            .map(|entity_index| entity_manager.get_entity(entity_index))
+            .collect();
+        entities.into_iter()
+    }
+
+    /// Entities with the component ordered by compare, without reordering the storage
+    pub fn sorted<C: 'static, F>(&self, entity_manager: &EntityManager<WorldId>, compare: F) -> IntoIter<Entity<WorldId>>
+        where F: Fn(&C, &C) -> Ordering
+    {
+        let list = &self.get_component_data::<C>().list;
+
+        // starting from storage order makes sorting an already sorted storage cheap
+        let mut entity_indices = self.get_ordered_indices::<C>();
+        entity_indices.sort_by(|a, b| compare(list.get(a).unwrap(), list.get(b).unwrap()));
+
+        let entities: Vec<Entity<WorldId>> = entity_indices.into_iter()
+            .map(|entity_index| entity_manager.get_entity(entity_index))
+            .collect();
+        entities.into_iter()
+    }
+
+    fn get_ordered_indices<C: 'static>(&self) -> Vec<usize> {
+        let component_data = self.get_component_data::<C>();
+        match component_data.list.ordered_indices() {
+            Some(entity_indices) => entity_indices,
+            None => self.get_entity_indices_with(component_data.index),
+        }
+    }
+
+    // *** Metadata ***
+
+    /// Allow the component to be printed by debug_entity
+    pub fn register_debug<C: Debug + 'static>(&mut self) {
+        let index = self.get_component_data::<C>().index;
+        self.component_infos[index].debug = Some(debug_component::<WorldId, C>);
+    }
+
+    /// Allow the component to be copied by clone_components and captured by snapshot
+    pub fn register_clone<C: Clone + 'static>(&mut self) {
+        let index = self.get_component_data::<C>().index;
+        self.component_infos[index].clone = Some(clone_component::<WorldId, C>);
+        self.component_infos[index].snapshot = Some(snapshot_components::<WorldId, C>);
+        self.component_infos[index].restore = Some(restore_components::<WorldId, C>);
+    }
+
+    /// Copy all cloneable components of source to target
+    /// Components that weren't registered with register_clone are skipped
+    pub fn clone_components(&mut self, source: &Entity<WorldId>, target: &Entity<WorldId>) {
+        for component_id in self.get_entity_components(source).into_iter() {
+            let clone = self.component_infos[component_id.0].clone;
+            if let Some(clone) = clone {
+                clone(self, source, target);
+            }
+        }
+    }
+
+    // *** Hashing ***
+
+    /// Include the component value in hash_entity
+    /// usize and isize fields hash differently on 32 and 64 bit peers, use fixed-width fields
+    pub fn register_hash<C: Hash + 'static>(&mut self) {
+        let index = self.get_component_data::<C>().index;
+        self.component_infos[index].hash = Some(hash_component::<WorldId, C>);
+    }
+
+    /// Feeds the entity, its component mask and its components registered with register_hash into hasher
+    /// Only depends on registration order, so it can be compared between processes
+    pub fn hash_entity(&self, entity: &Entity<WorldId>, hasher: &mut SipHasher) {
+        // fixed-width, so 32 and 64 bit peers agree
+        (entity.index() as u64).hash(hasher);
+        (entity.version() as u64).hash(hasher);
+
+        for component_id in self.get_entity_components(entity).into_iter() {
+            (component_id.0 as u64).hash(hasher);
+
+            if let Some(hash) = self.component_infos[component_id.0].hash {
+                hash(self, entity, hasher);
+            }
+        }
+    }
+
+    // *** Serialization ***
+
+    /// Allow the component to be encoded to and decoded from bytes and JSON
+    /// Saved data identifies the component by name instead of the type name, so it survives renaming or moving the type
+    pub fn register_serialize<C: Encodable + Decodable + 'static>(&mut self, name: &str) {
+        let index = self.get_component_data::<C>().index;
+
+        match self.get_component_id_by_serialize_name(name) {
+            Some(ComponentId(other_index)) if other_index != index => panic!("Tried to register serialize name twice"),
+            _ => {},
+        }
+
+        self.component_infos[index].serialize_name = Some(name.to_string());
+        self.component_infos[index].encode = Some(encode_component::<WorldId, C>);
+        self.component_infos[index].decode = Some(decode_component::<WorldId, C>);
+        self.component_infos[index].can_decode = Some(can_decode_component::<C>);
+        self.component_infos[index].to_json = Some(component_to_json::<WorldId, C>);
+        self.component_infos[index].from_json = Some(component_from_json::<WorldId, C>);
+        self.component_infos[index].migrate = Some(migrate_component::<WorldId, C>);
+    }
+
+    /// Version the component is saved with, see register_migration
+    pub fn register_schema<C: 'static>(&mut self, version: u32) {
+        let index = self.get_component_data::<C>().index;
+        self.component_infos[index].schema_version = version;
+    }
+
+    /// Decode components saved with schema version from_version as Old and convert them
+    pub fn register_migration<C, Old, F>(&mut self, from_version: u32, migration: F)
+        where C: 'static, Old: Decodable, F: Fn(Old) -> C + 'static
+    {
+        if from_version >= self.component_infos[self.get_component_data::<C>().index].schema_version {
+            panic!("Tried to register migration from current or newer schema version");
+        }
+
+        let migration: Box<Fn(&[u8]) -> Option<C> + 'static> = Box::new(move |bytes: &[u8]| {
+            bincode::decode::<Old>(bytes).ok().map(|old| migration(old))
+        });
+        self.get_component_data_mut::<C>().migrations.push((from_version, migration));
+    }
+
+    /// None if the entity doesn't have the component or it wasn't registered with register_serialize
+    pub fn component_to_json(&self, entity: &Entity<WorldId>, component_id: ComponentId) -> Option<Json> {
+        if !self.has_component_id(entity, component_id) {
+            return None;
+        }
+
+        self.component_infos[component_id.0].to_json.map(|to_json| to_json(self, entity))
+    }
+
+    /// Add or replace component from JSON
+    /// False if it couldn't be decoded or wasn't registered with register_serialize
+    pub fn component_from_json(&mut self, entity: &Entity<WorldId>, component_id: ComponentId, json: Json) -> bool {
+        match self.component_infos[component_id.0].from_json {
+            Some(from_json) => from_json(self, entity, json),
+            None => false,
+        }
+    }
+
+    /// None if the entity doesn't have the component or it wasn't registered with register_serialize
+    pub fn encode_component(&self, entity: &Entity<WorldId>, component_id: ComponentId) -> Option<Vec<u8>> {
+        if !self.has_component_id(entity, component_id) {
+            return None;
+        }
+
+        self.component_infos[component_id.0].encode.map(|encode| encode(self, entity))
+    }
+
+    /// Add or replace component from bytes
+    /// False if they couldn't be decoded or it wasn't registered with register_serialize
+    pub fn decode_component(&mut self, entity: &Entity<WorldId>, component_id: ComponentId, bytes: &[u8]) -> bool {
+        match self.component_infos[component_id.0].decode {
+            Some(decode) => decode(self, entity, bytes),
+            None => false,
+        }
+    }
+
+    /// Whether decode_component would succeed, without changing anything
+    /// False for unknown component ids, so it can check untrusted data
+    pub fn can_decode_component(&self, component_id: ComponentId, bytes: &[u8]) -> bool {
+        match self.component_infos.get(component_id.0).and_then(|component_info| component_info.can_decode) {
+            Some(can_decode) => can_decode(bytes),
+            None => false,
+        }
+    }
+
+    /// Add or replace component from bytes encoded with schema version, migrating older versions
+    /// False if they couldn't be decoded, there's no migration from the version or it wasn't registered with register_serialize
+    pub fn decode_component_version(&mut self, entity: &Entity<WorldId>, component_id: ComponentId, version: u32, bytes: &[u8]) -> bool {
+        if version == self.component_infos[component_id.0].schema_version {
+            return self.decode_component(entity, component_id, bytes);
+        }
+
+        match self.component_infos[component_id.0].migrate {
+            Some(migrate) => migrate(self, entity, version, bytes),
+            None => false,
+        }
+    }
+
+    // *** Snapshots ***
+
+    /// Copy of all component masks and components
+    /// Components that weren't registered with register_clone, including dynamic components, aren't copied
+    pub fn snapshot(&self) -> ComponentSnapshot<WorldId> {
+        let components = self.component_infos.iter()
+            .map(|component_info| component_info.snapshot.map(|snapshot| snapshot(self)))
+            .collect();
+
+        ComponentSnapshot {
+            phantom: PhantomData,
+            entity_component_masks: self.entity_component_masks.clone(),
+            components: components,
+        }
+    }
+
+    /// Replace all component masks and components with those of the snapshot
+    /// Hooks aren't run, but restored components count as added and changed
+    /// Components that weren't copied keep their current values, so entities that don't exist in the snapshot
+    /// must be destroyed first, see World::restore
+    pub fn restore(&mut self, snapshot: &ComponentSnapshot<WorldId>) {
+        assert!(snapshot.components.len() == self.component_infos.len(), "Tried to restore snapshot with different components");
+
+        let mut entity_component_masks = snapshot.entity_component_masks.clone();
+
+        for (component_index, components) in snapshot.components.iter().enumerate() {
+            match *components {
+                Some(ref components) => {
+                    let restore = self.component_infos[component_index].restore.unwrap();
+                    restore(self, &**components);
+                },
+                None => for (entity_index, entity_component_mask) in entity_component_masks.iter_mut().enumerate() {
+                    let has_component = self.entity_component_masks.get(entity_index)
+                        .and_then(|current_mask| current_mask.get(component_index))
+                        .unwrap_or(false);
+                    entity_component_mask.set(component_index, has_component);
+                },
+            }
+        }
+
+        self.entity_component_masks = entity_component_masks;
+
+        for query_index in 0..self.query_caches.len() {
+            self.rebuild_query_cache(query_index);
+        }
+    }
+
+    // Entity indices of all entities with the component
+    fn get_entity_indices_with(&self, component_index: usize) -> Vec<usize> {
+        self.entity_component_masks.iter()
+            .enumerate()
+            .filter(|&(_, entity_component_mask)| entity_component_mask.get(component_index).unwrap_or(false))
+            .map(|(entity_index, _)| entity_index)
+            .collect()
+    }
+
+    // *** Moving between ComponentManagers ***
+
+    /// Allow the component to be moved to ComponentManager<To>, converting its Entity fields
+    /// The conversion is looked up by type, hence the 'static bounds that rule out Brand
+    pub fn register_map_entities<C, To>(&mut self) where C: MapEntities<WorldId, To> + 'static, WorldId: 'static, To: 'static {
+        let index = self.get_component_data::<C>().index;
+        self.component_infos[index].map_entities.push(Box::new(MapEntitiesFn {
+            map_entities: map_entities::<WorldId, To, C>,
+            type_id: TypeId::of::<C::Output>(),
+        }));
+    }
+
+    /// Move all components of entity to target_entity in another ComponentManager
+    /// Components are matched by type, or by name for dynamic components, and must be registered in both
+    /// Components registered with register_map_entities are converted using entity_map
+    pub fn move_components<To>(&mut self, entity: &Entity<WorldId>, target: &mut ComponentManager<To>, target_entity: &Entity<To>, entity_map: &EntityMap<WorldId, To>) where WorldId: 'static, To: 'static {
+        // before taking anything, so no component is lost when one isn't registered in target
+        let component_ids: Vec<(ComponentId, ComponentId)> = self.get_entity_components(entity).into_iter()
+            .map(|component_id| match self.get_target_component_id(component_id, target) {
+                Some(target_component_id) => (component_id, target_component_id),
+                None => panic!("Tried to move unregistered component"),
+            })
+            .collect();
+
+        for (component_id, target_component_id) in component_ids.into_iter() {
+            let take = self.component_infos[component_id.0].take;
+            let component = match take(self, component_id.0, entity) {
+                Some(component) => component,
+                None => continue,
+            };
+
+            let component = match self.get_map_entities::<To>(component_id) {
+                Some((map_entities, _)) => map_entities(component, entity_map),
+                None => component,
+            };
+
+            let insert = target.component_infos[target_component_id.0].insert;
+            insert(target, target_component_id.0, target_entity, component);
+        }
+    }
+
+    fn get_map_entities<To>(&self, component_id: ComponentId) -> Option<(fn(Box<Any>, &EntityMap<WorldId, To>) -> Box<Any>, TypeId)> where WorldId: 'static, To: 'static {
+        self.component_infos[component_id.0].map_entities.iter()
+            .filter_map(|map_entities| map_entities.downcast_ref::<MapEntitiesFn<WorldId, To>>())
+            .map(|map_entities| (map_entities.map_entities, map_entities.type_id))
+            .next()
+    }
+
+    // the component of target a component is moved to, converted by register_map_entities
+    fn get_target_component_id<To>(&self, component_id: ComponentId, target: &ComponentManager<To>) -> Option<ComponentId> where WorldId: 'static, To: 'static {
+        let component_info = &self.component_infos[component_id.0];
+
+        match (self.get_map_entities::<To>(component_id), component_info.type_id) {
+            (Some((_, type_id)), _) | (None, Some(type_id)) => target.get_component_id_by_type(type_id),
+            (None, None) => target.get_dynamic_component_id(&component_info.name),
+        }
+    }
+
+    /// Type name for typed components, registered name for dynamic components
+    pub fn get_component_id_by_name(&self, name: &str) -> Option<ComponentId> {
+        self.component_infos.iter()
+            .position(|component_info| component_info.name == name)
+            .map(|index| ComponentId(index))
+    }
+
+    /// Name given to register_serialize
+    pub fn get_component_id_by_serialize_name(&self, name: &str) -> Option<ComponentId> {
+        self.component_infos.iter()
+            .position(|component_info| component_info.serialize_name.as_ref().map_or(false, |serialize_name| *serialize_name == name))
+            .map(|index| ComponentId(index))
+    }
+
+    pub fn get_component_id_by_type(&self, type_id: TypeId) -> Option<ComponentId> {
+        self.component_infos.iter()
+            .position(|component_info| component_info.type_id == Some(type_id))
+            .map(|index| ComponentId(index))
+    }
+
+    pub fn get_component_info(&self, component_id: ComponentId) -> &ComponentInfo<WorldId> {
+        &self.component_infos[component_id.0]
+    }
+
+    /// Registered components, the position in the slice is the component index
+    pub fn get_component_infos(&self) -> &[ComponentInfo<WorldId>] {
+        &self.component_infos[..]
+    }
+
+    pub fn get_entity_components(&self, entity: &Entity<WorldId>) -> Vec<ComponentId> {
+        self.entity_component_masks[entity.index()].iter()
+            .enumerate()
+            .filter(|&(_, has_component)| has_component)
+            .map(|(component_index, _)| ComponentId(component_index))
+            .collect()
+    }
+
+    /// Prints as entity 42: Position { x: 1.0 }, Velocity { .. }
+    /// Components that weren't registered with register_debug are printed as Name { .. }
+    pub fn debug_entity(&'a self, entity: &'a Entity<WorldId>) -> EntityDebug<'a, WorldId> {
+        EntityDebug {
+            component_manager: self,
+            entity: entity,
+        }
+    }
+
+    // *** Dynamic components ***
+
+    pub fn register_dynamic_component(&mut self, name: &str) -> ComponentId {
+        if self.get_dynamic_component_id(name).is_some() {
+            panic!("Tried to register dynamic component twice");
+        }
+
+        let index = self.add_component_index(ComponentInfo {
+            name: name.to_string(),
+            type_id: None,
+            storage: StorageKind::Dynamic,
+            schema_version: 0,
+            serialize_name: None,
+            remove: remove_dynamic_component::<WorldId>,
+            clear_removed: clear_removed_dynamic::<WorldId>,
+            debug: None,
+            clone: None,
+            take: take_dynamic_component::<WorldId>,
+            insert: insert_dynamic_component::<WorldId>,
+            map_entities: Vec::new(),
+            snapshot: None,
+            restore: None,
+            hash: None,
+            is_changed_since: is_dynamic_component_changed_since::<WorldId>,
+            encode: None,
+            decode: None,
+            can_decode: None,
+            to_json: None,
+            from_json: None,
+            migrate: None,
+            on_add: None,
+            on_replace: None,
+            on_remove: None,
+            requires: Vec::new(),
+            required_by: Vec::new(),
+        });
+
+        self.dynamic_component_data.insert(index, DynamicComponentData {
+            name: name.to_string(),
+            list: VecMap::new(),
+            removed: Vec::new(),
+        });
+
+        ComponentId(index)
+    }
+
+    pub fn get_dynamic_component_id(&self, name: &str) -> Option<ComponentId> {
+        self.dynamic_component_data.iter()
+            .find(|&(_, dynamic_component_data)| dynamic_component_data.name == name)
+            .map(|(index, _)| ComponentId(index))
+    }
+
+    /// Add or replace dynamic component on entity
+    pub fn assign_dynamic_component(&mut self, entity: &Entity<WorldId>, component_id: ComponentId, component: Box<Any>) {
+        self.get_dynamic_component_data_mut(component_id).list.insert(entity.index(), component);
+        self.entity_component_masks[entity.index()].set(component_id.0, true);
+        self.mask_changed(entity.index());
+    }
+
+    /// Remove dynamic component from entity, keeping the value in removed
+    pub fn remove_dynamic_component(&mut self, entity: &Entity<WorldId>, component_id: ComponentId) {
+        if !self.has_component_id(entity, component_id) {
+            return;
+        }
+
+        let component = self.detach_dynamic_component(entity, component_id);
+        let tick = self.tick;
+        self.get_dynamic_component_data_mut(component_id).removed.push((entity.index(), entity.version(), tick, component));
+    }
+
+    /// Remove dynamic component from entity and return it
+    /// The removal is still visible through removed_dynamic, but without the value
+    pub fn take_dynamic_component(&mut self, entity: &Entity<WorldId>, component_id: ComponentId) -> Option<Box<Any>> {
+        if !self.has_component_id(entity, component_id) {
+            return None;
+        }
+
+        let component = self.detach_dynamic_component(entity, component_id);
+        let tick = self.tick;
+        self.get_dynamic_component_data_mut(component_id).removed.push((entity.index(), entity.version(), tick, None));
+        component
+    }
+
+    fn detach_dynamic_component(&mut self, entity: &Entity<WorldId>, component_id: ComponentId) -> Option<Box<Any>> {
+        let component = self.get_dynamic_component_data_mut(component_id).list.remove(&entity.index());
+        self.entity_component_masks[entity.index()].set(component_id.0, false);
+        self.mask_changed(entity.index());
+        component
+    }
+
+    pub fn get_dynamic_component(&self, entity: &Entity<WorldId>, component_id: ComponentId) -> Option<&Any> {
+        if !self.has_component_id(entity, component_id) {
+            return None;
+        }
+
+        self.get_dynamic_component_data(component_id).list.get(&entity.index()).map(|component| &**component)
+    }
+
+    pub fn get_dynamic_component_mut(&mut self, entity: &Entity<WorldId>, component_id: ComponentId) -> Option<&mut Any> {
+        if !self.has_component_id(entity, component_id) {
+            return None;
+        }
+
+        self.get_dynamic_component_data_mut(component_id).list.get_mut(&entity.index()).map(|component| &mut **component)
+    }
+
+    /// Dynamic components removed from entities, see removed
+    pub fn removed_dynamic(&'a self, component_id: ComponentId) -> DynamicRemovedIterator<'a, WorldId> {
+        self.removed_dynamic_since(component_id, 0)
+    }
+
+    /// Dynamic components removed after tick, see removed_since
+    pub fn removed_dynamic_since(&'a self, component_id: ComponentId, tick: usize) -> DynamicRemovedIterator<'a, WorldId> {
+        DynamicRemovedIterator {
+            phantom: PhantomData,
+            removed: self.get_dynamic_component_data(component_id).removed.iter(),
+            tick: tick,
+        }
+    }
+
+    fn get_dynamic_component_data(&self, component_id: ComponentId) -> &DynamicComponentData {
+        if let Some(dynamic_component_data) = self.dynamic_component_data.get(&component_id.0) {
+            dynamic_component_data
+        } else {
+            panic!("Tried to get unregistered dynamic component");
+        }
+    }
+
+    fn get_dynamic_component_data_mut(&mut self, component_id: ComponentId) -> &mut DynamicComponentData {
+        if let Some(dynamic_component_data) = self.dynamic_component_data.get_mut(&component_id.0) {
+            dynamic_component_data
+        } else {
+            panic!("Tried to get unregistered dynamic component");
+        }
+    }
+
+    // *** Removed components ***
+
+    /// Components removed from entities, including by destroying them
+    /// They're kept until every system ran after the removal, see SystemManager::update
+    pub fn removed<C: 'static>(&'a self) -> RemovedIterator<'a, WorldId, C> {
+        self.removed_since::<C>(0)
+    }
+
+    /// Components removed after tick, e.g. Control::last_run so a system sees every removal once
+    pub fn removed_since<C: 'static>(&'a self, tick: usize) -> RemovedIterator<'a, WorldId, C> {
+        RemovedIterator {
+            phantom: PhantomData,
+            removed: self.get_component_data::<C>().removed.iter(),
+            tick: tick,
+        }
+    }
+
+    /// Drop all removed components
+    pub fn clear_removed(&mut self) {
+        let tick = self.tick;
+        self.clear_removed_until(tick);
+    }
+
+    /// Drop components removed at or before tick
+    pub fn clear_removed_until(&mut self, tick: usize) {
+        for component_index in 0..self.component_infos.len() {
+            let clear_removed = self.component_infos[component_index].clear_removed;
+            clear_removed(self, component_index, tick);
+        }
+    }
+
+    // *** Change detection ***
+
+    pub fn tick(&self) -> usize {
+        self.tick
+    }
+
+    pub fn increment_tick(&mut self) -> usize {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// True if component was assigned to entity after tick
+    pub fn is_added_since<C: 'static>(&self, entity: &Entity<WorldId>, tick: usize) -> bool {
+        let component_data = self.get_component_data::<C>();
+        self.has_component_from_data(entity, component_data)
+        && component_data.added_ticks.get(&entity.index()).map_or(false, |added| *added > tick)
+    }
+
+    /// True if component was assigned, replaced or mutably borrowed after tick
+    pub fn is_changed_since<C: 'static>(&self, entity: &Entity<WorldId>, tick: usize) -> bool {
+        let component_data = self.get_component_data::<C>();
+        self.has_component_from_data(entity, component_data)
+        && component_data.changed_ticks.get(&entity.index()).map_or(false, |changed| *changed > tick)
+    }
+
+    /// Like is_changed_since, dynamic components always count as changed
+    pub fn is_changed_since_id(&self, entity: &Entity<WorldId>, component_id: ComponentId, tick: usize) -> bool {
+        self.has_component_id(entity, component_id)
+        && (self.component_infos[component_id.0].is_changed_since)(self, entity, tick)
+    }
+
+    pub fn added<C: 'static>(&'a self, entity_manager: &'a EntityManager<WorldId>, tick: usize) -> ChangedIterator<'a, WorldId, C> {
+        ChangedIterator {
+            phantom: PhantomData,
+            component_manager: self,
+            entities: entity_manager.entities(),
+            tick: tick,
+            added_only: true,
+        }
+    }
+
+    pub fn changed<C: 'static>(&'a self, entity_manager: &'a EntityManager<WorldId>, tick: usize) -> ChangedIterator<'a, WorldId, C> {
+        ChangedIterator {
+            phantom: PhantomData,
+            component_manager: self,
+            entities: entity_manager.entities(),
+            tick: tick,
+            added_only: false,
+        }
+    }
+}
+
+/// Opaque copy of the state of a ComponentManager
+pub struct ComponentSnapshot<WorldId> {
+    phantom: PhantomData<WorldId>,
+    entity_component_masks: Vec<BitVec>,
+    // by component index, Vec<(usize, C)> of entity index and component, None if not cloneable
+    components: Vec<Option<Box<Any>>>,
+}
+
+pub struct RemovedIterator<'a, WorldId, C: 'static> {
+    phantom: PhantomData<WorldId>,
+    removed: Iter<'a, (usize, usize, usize, Option<C>)>,
+    tick: usize,
+}
+
+impl<'a, WorldId, C: 'static> Iterator for RemovedIterator<'a, WorldId, C> {
+    type Item = (Entity<WorldId>, Option<&'a C>);
+
+    fn next(&mut self) -> Option<(Entity<WorldId>, Option<&'a C>)> {
+        for &(index, version, tick, ref component) in self.removed.by_ref() {
+            if tick > self.tick {
+                return Some((Entity::new(index, version), component.as_ref()));
+            }
+        }
+
+        None
+    }
+}
+
+pub struct EntityDebug<'a, WorldId: 'a> {
+    component_manager: &'a ComponentManager<WorldId>,
+    entity: &'a Entity<WorldId>,
+}
+
+impl<'a, WorldId> Debug for EntityDebug<'a, WorldId> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), Error> {
+        try!(write!(formatter, "entity {}:", self.entity.index()));
+
+        for (i, component_id) in self.component_manager.get_entity_components(self.entity).into_iter().enumerate() {
+            try!(write!(formatter, "{}", if i == 0 { " " } else { ", " }));
+
+            let component_info = self.component_manager.get_component_info(component_id);
+            match component_info.debug {
+                Some(debug) => try!(debug(self.component_manager, self.entity, formatter)),
+                None => try!(write!(formatter, "{} {{ .. }}", short_name(&component_info.name))),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct DynamicRemovedIterator<'a, WorldId> {
+    phantom: PhantomData<WorldId>,
+    removed: Iter<'a, (usize, usize, usize, Option<Box<Any>>)>,
+    tick: usize,
+}
+
+impl<'a, WorldId> Iterator for DynamicRemovedIterator<'a, WorldId> {
+    type Item = (Entity<WorldId>, Option<&'a Any>);
+
+    fn next(&mut self) -> Option<(Entity<WorldId>, Option<&'a Any>)> {
+        for &(index, version, tick, ref component) in self.removed.by_ref() {
+            if tick > self.tick {
+                return Some((Entity::new(index, version), component.as_ref().map(|component| &**component)));
+            }
+        }
+
+        None
+    }
+}
+
+pub struct ChangedIterator<'a, WorldId: 'a, C: 'static> {
+    phantom: PhantomData<C>,
+    component_manager: &'a ComponentManager<WorldId>,
+    entities: EntityIterator<'a, WorldId>,
+    tick: usize,
+    added_only: bool,
+}
+
+impl<'a, WorldId, C: 'static> Iterator for ChangedIterator<'a, WorldId, C> {
+    type Item = Entity<WorldId>;
+
+    fn next(&mut self) -> Option<Entity<WorldId>> {
+        for entity in self.entities.by_ref() {
+            let found = if self.added_only {
+                self.component_manager.is_added_since::<C>(&entity, self.tick)
+            } else {
+                self.component_manager.is_changed_since::<C>(&entity, self.tick)
+            };
+
+            if found {
+                return Some(entity);
+            }
+        }
+
+        None
+    }
+}
+
+pub struct FindIterator<'a, WorldId: 'a, C: 'static> {
+    entity_manager: &'a EntityManager<WorldId>,
+    component_data: &'a ComponentData<C>,
+    entity_component_masks: Enumerate<Iter<'a, BitVec>>,
+    value: &'a C,
+}
+
+impl<'a, WorldId, C: PartialEq + 'static> Iterator for FindIterator<'a, WorldId, C> {
+    type Item = Entity<WorldId>;
+
+    fn next(&mut self) -> Option<Entity<WorldId>> {
+        let component_index = self.component_data.index;
+        for (entity_index, entity_component_mask) in self.entity_component_masks.by_ref() {
+            if entity_component_mask.get(component_index).unwrap_or(false)
+            && self.component_data.list.get(&entity_index) == Some(self.value) {
+                return Some(self.entity_manager.get_entity(entity_index));
+            }
+        }
+
+        None
+    }
+}
+
+pub struct QueryIterator<'a, WorldId: 'a> {
+    entity_manager: &'a EntityManager<WorldId>,
+    entity_indices: Iter<'a, usize>,
+}
+
+impl<'a, WorldId> Iterator for QueryIterator<'a, WorldId> {
+    type Item = Entity<WorldId>;
+
+    fn next(&mut self) -> Option<Entity<WorldId>> {
+        self.entity_indices.next().map(|&entity_index| self.entity_manager.get_entity(entity_index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::{ TypeId };
+    use super::{
+        ComponentManager,
+        ComponentHooks,
+        DenseList,
+        StorageKind,
+    };
+    use index::{ Spatial };
+    use entity::{ EntityManager, Entity };
+    use thread_pool::{ ThreadPool };
+    use std::collections::{ VecMap, HashMap };
+
+    #[test]
+    fn register_components() {
+        struct WorldId1;
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+
+        // test different datastructures
+        #[derive(PartialEq, Debug)]
+        struct UnitComponent;
+        component_manager.register_component::<UnitComponent>(Box::new(VecMap::new()));
+
+        #[derive(PartialEq, Debug)]
+        struct TupleComponent(isize);
+        component_manager.register_component::<TupleComponent>(Box::new(HashMap::new()));
+
+        #[derive(PartialEq, Debug)]
+        struct Component {
+            field: isize,
+        }
+        component_manager.register_component::<Component>(Box::new(VecMap::new()));
+    }
+
+    #[test]
+    fn unassigned_components() {
+        struct WorldId1;
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+
+        // test different datastructures
+        #[derive(PartialEq, Debug)]
+        struct UnitComponent;
+        component_manager.register_component::<UnitComponent>(Box::new(VecMap::new()));
+
+        #[derive(PartialEq, Debug)]
+        struct TupleComponent(isize);
+        component_manager.register_component::<TupleComponent>(Box::new(HashMap::new()));
+
+        #[derive(PartialEq, Debug)]
+        struct Component {
+            field: isize,
+        }
+        component_manager.register_component::<Component>(Box::new(VecMap::new()));
+
+        let entity = entity_manager.create_entity();
+        component_manager.entity_created(&entity);
+
+        // test unassigned components are None
+        let unit_component = component_manager.get_component::<UnitComponent>(&entity);
+        assert!(unit_component.is_none());
+
+        let tuple_component = component_manager.get_component::<TupleComponent>(&entity);
+        assert!(tuple_component.is_none());
+
+        let component = component_manager.get_component::<Component>(&entity);
+        assert!(component.is_none());
+    }
+
+    #[test]
+    fn assigned_components() {
+        struct WorldId1;
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+
+        // test different datastructures
+        #[derive(PartialEq, Debug)]
+        struct UnitComponent;
+        component_manager.register_component::<UnitComponent>(Box::new(VecMap::new()));
+
+        #[derive(PartialEq, Debug)]
+        struct TupleComponent(isize);
+        component_manager.register_component::<TupleComponent>(Box::new(HashMap::new()));
+
+        #[derive(PartialEq, Debug)]
+        struct Component {
+            field: isize,
+        }
+        component_manager.register_component::<Component>(Box::new(VecMap::new()));
+
+        let entity = entity_manager.create_entity();
+        component_manager.entity_created(&entity);
+
+        component_manager.assign_component::<UnitComponent>(&entity, UnitComponent);
+        component_manager.assign_component::<TupleComponent>(&entity, TupleComponent(1));
+        component_manager.assign_component::<Component>(&entity, Component { field: 1 });
+
+        // test assigned components
+        let unit_component = component_manager.get_component::<UnitComponent>(&entity);
+        assert_eq!(unit_component.unwrap(), &UnitComponent);
+
+        let tuple_component = component_manager.get_component::<TupleComponent>(&entity);
+        assert_eq!(tuple_component.unwrap(), &TupleComponent(1));
+
+        let component = component_manager.get_component::<Component>(&entity);
+        assert_eq!(component.unwrap(), &Component { field: 1 });
+    }
+
+    #[test]
+    fn changed_components() {
+        struct WorldId1;
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+
+        #[derive(PartialEq, Debug)]
+        struct Component {
+            field: isize,
+        }
+        component_manager.register_component::<Component>(Box::new(VecMap::new()));
+
+        let entity1 = entity_manager.create_entity();
+        component_manager.entity_created(&entity1);
+        let entity2 = entity_manager.create_entity();
+        component_manager.entity_created(&entity2);
+
+        component_manager.assign_component::<Component>(&entity1, Component { field: 1 });
+        component_manager.assign_component::<Component>(&entity2, Component { field: 2 });
+
+        let last_run = component_manager.tick();
+        component_manager.increment_tick();
+
+        assert!(!component_manager.is_changed_since::<Component>(&entity1, last_run));
+        component_manager.get_component_mut::<Component>(&entity1).unwrap().field = 3;
+        assert!(component_manager.is_changed_since::<Component>(&entity1, last_run));
+        assert!(!component_manager.is_added_since::<Component>(&entity1, last_run));
+
+        let changed: Vec<_> = component_manager.changed::<Component>(&entity_manager, last_run).collect();
+        assert_eq!(changed, vec![entity1]);
+    }
+
+    #[test]
+    fn added_components() {
+        struct WorldId1;
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+
+        #[derive(PartialEq, Debug)]
+        struct UnitComponent;
+        component_manager.register_component::<UnitComponent>(Box::new(VecMap::new()));
+
+        let entity = entity_manager.create_entity();
+        component_manager.entity_created(&entity);
+
+        let last_run = component_manager.tick();
+        component_manager.increment_tick();
+
+        component_manager.assign_component::<UnitComponent>(&entity, UnitComponent);
+        assert!(component_manager.is_added_since::<UnitComponent>(&entity, last_run));
+
+        let added: Vec<_> = component_manager.added::<UnitComponent>(&entity_manager, last_run).collect();
+        assert_eq!(added, vec![entity]);
+    }
+
+    #[test]
+    fn removed_components() {
+        struct WorldId1;
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+
+        #[derive(PartialEq, Debug)]
+        struct TupleComponent(isize);
+        component_manager.register_component::<TupleComponent>(Box::new(HashMap::new()));
+
+        let entity1 = entity_manager.create_entity();
+        component_manager.entity_created(&entity1);
+        let entity2 = entity_manager.create_entity();
+        component_manager.entity_created(&entity2);
+
+        component_manager.assign_component::<TupleComponent>(&entity1, TupleComponent(1));
+        component_manager.assign_component::<TupleComponent>(&entity2, TupleComponent(2));
+
+        component_manager.remove_component::<TupleComponent>(&entity1);
+        component_manager.entity_destroyed(&entity2);
+        entity_manager.destroy_entity(entity2.clone());
+
+        let removed: Vec<_> = component_manager.removed::<TupleComponent>().collect();
+        assert_eq!(removed, vec![
+            (entity1.clone(), Some(&TupleComponent(1))),
+            (entity2, Some(&TupleComponent(2))),
+        ]);
+        assert!(component_manager.get_component::<TupleComponent>(&entity1).is_none());
+
+        component_manager.clear_removed();
+        assert_eq!(component_manager.removed::<TupleComponent>().count(), 0);
+    }
+
+    #[test]
+    fn dynamic_components() {
+        struct WorldId1;
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+
+        #[derive(PartialEq, Debug)]
+        struct UnitComponent;
+        component_manager.register_component::<UnitComponent>(Box::new(VecMap::new()));
+        let health = component_manager.register_dynamic_component("health");
+
+        assert!(health != component_manager.get_component_id::<UnitComponent>());
+        assert_eq!(component_manager.get_dynamic_component_id("health"), Some(health));
+
+        let entity = entity_manager.create_entity();
+        component_manager.entity_created(&entity);
+
+        component_manager.assign_component::<UnitComponent>(&entity, UnitComponent);
+        component_manager.assign_dynamic_component(&entity, health, Box::new(10isize));
+        assert!(component_manager.has_component_id(&entity, health));
+
+        *component_manager.get_dynamic_component_mut(&entity, health).unwrap().downcast_mut::<isize>().unwrap() -= 1;
+        assert_eq!(component_manager.get_dynamic_component(&entity, health).unwrap().downcast_ref::<isize>(), Some(&9));
+
+        component_manager.entity_destroyed(&entity);
+        assert!(!component_manager.has_component_id(&entity, health));
+        assert!(component_manager.get_dynamic_component(&entity, health).is_none());
+        assert_eq!(component_manager.removed_dynamic(health).count(), 1);
+
+        let tick = component_manager.increment_tick();
+        assert_eq!(component_manager.removed_dynamic_since(health, tick).count(), 0);
+
+        let entity = entity_manager.create_entity();
+        component_manager.entity_created(&entity);
+        component_manager.assign_dynamic_component(&entity, health, Box::new(5isize));
+        component_manager.increment_tick();
+        component_manager.remove_dynamic_component(&entity, health);
+        assert_eq!(component_manager.removed_dynamic(health).count(), 2);
+        assert_eq!(component_manager.removed_dynamic_since(health, tick).count(), 1);
+    }
+
+    #[test]
+    fn component_infos() {
+        struct WorldId1;
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+
+        struct UnitComponent;
+        component_manager.register_component::<UnitComponent>(Box::new(VecMap::new()));
+        let health = component_manager.register_dynamic_component("health");
+
+        let unit_component = component_manager.get_component_id::<UnitComponent>();
+        assert_eq!(component_manager.get_component_info(unit_component).type_id, Some(TypeId::of::<UnitComponent>()));
+        assert_eq!(component_manager.get_component_info(unit_component).storage, StorageKind::VecMap);
+        assert_eq!(component_manager.get_component_info(health).name, "health");
+        assert_eq!(component_manager.get_component_info(health).storage, StorageKind::Dynamic);
+        assert_eq!(component_manager.get_component_infos().len(), 2);
+    }
+
+    #[test]
+    fn debug_entity() {
         struct WorldId1;
         let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
         let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
 
-        // test different datastructures
+        #[derive(Debug)]
+        struct Position {
+            x: isize,
+        }
+        component_manager.register_component::<Position>(Box::new(VecMap::new()));
+        component_manager.register_debug::<Position>();
+
+        struct Velocity;
+        component_manager.register_component::<Velocity>(Box::new(HashMap::new()));
+
+        let entity = entity_manager.create_entity();
+        component_manager.entity_created(&entity);
+        component_manager.assign_component(&entity, Position { x: 1 });
+        component_manager.assign_component(&entity, Velocity);
+
+        assert_eq!(format!("{:?}", component_manager.debug_entity(&entity)), "entity 0: Position { x: 1 }, Velocity { .. }");
+    }
+
+    #[test]
+    fn clone_components() {
+        struct WorldId1;
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+
+        #[derive(Clone, PartialEq, Debug)]
+        struct TupleComponent(isize);
+        component_manager.register_component::<TupleComponent>(Box::new(HashMap::new()));
+        component_manager.register_clone::<TupleComponent>();
+
         #[derive(PartialEq, Debug)]
         struct UnitComponent;
         component_manager.register_component::<UnitComponent>(Box::new(VecMap::new()));
 
-        #[derive(PartialEq, Debug)]
+        let source = entity_manager.create_entity();
+        component_manager.entity_created(&source);
+        let target = entity_manager.create_entity();
+        component_manager.entity_created(&target);
+
+        component_manager.assign_component(&source, TupleComponent(1));
+        component_manager.assign_component(&source, UnitComponent);
+
+        component_manager.clone_components(&source, &target);
+
+        assert_eq!(component_manager.get_component::<TupleComponent>(&target), Some(&TupleComponent(1)));
+        assert!(!component_manager.has_component::<UnitComponent>(&target));
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        struct WorldId1;
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+
+        #[derive(Clone, PartialEq, Debug)]
         struct TupleComponent(isize);
         component_manager.register_component::<TupleComponent>(Box::new(HashMap::new()));
+        component_manager.register_clone::<TupleComponent>();
 
-        #[derive(PartialEq, Debug)]
-        struct Component {
-            field: isize,
-        }
-        component_manager.register_component::<Component>(Box::new(VecMap::new()));
+        let entity1 = entity_manager.create_entity();
+        component_manager.entity_created(&entity1);
+        let entity2 = entity_manager.create_entity();
+        component_manager.entity_created(&entity2);
 
-        let entity = entity_manager.create_entity();
-        component_manager.entity_created(&entity);
+        component_manager.assign_component(&entity1, TupleComponent(1));
 
-        // test unassigned components are None
-        let unit_component = component_manager.get_component::<UnitComponent>(&entity);
-        assert!(unit_component.is_none());
+        let snapshot = component_manager.snapshot();
 
-        let tuple_component = component_manager.get_component::<TupleComponent>(&entity);
-        assert!(tuple_component.is_none());
+        component_manager.get_component_mut::<TupleComponent>(&entity1).unwrap().0 = 2;
+        component_manager.assign_component(&entity2, TupleComponent(3));
 
-        let component = component_manager.get_component::<Component>(&entity);
-        assert!(component.is_none());
+        component_manager.restore(&snapshot);
+
+        assert_eq!(component_manager.get_component::<TupleComponent>(&entity1), Some(&TupleComponent(1)));
+        assert_eq!(component_manager.get_component::<TupleComponent>(&entity2), None);
     }
 
     #[test]
-    fn assigned_components() {
+    fn snapshot_without_register_clone() {
         struct WorldId1;
         let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
         let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
 
-        // test different datastructures
         #[derive(PartialEq, Debug)]
         struct UnitComponent;
         component_manager.register_component::<UnitComponent>(Box::new(VecMap::new()));
+        let dynamic = component_manager.register_dynamic_component("Script");
+
+        let entity = entity_manager.create_entity();
+        component_manager.entity_created(&entity);
+
+        let snapshot = component_manager.snapshot();
+
+        component_manager.assign_component(&entity, UnitComponent);
+        component_manager.assign_dynamic_component(&entity, dynamic, Box::new(1isize));
+
+        component_manager.restore(&snapshot);
+
+        assert_eq!(component_manager.get_component::<UnitComponent>(&entity), Some(&UnitComponent));
+        assert!(component_manager.has_component_id(&entity, dynamic));
+    }
+
+    #[test]
+    fn snapshot_restore_keeps_order() {
+        struct WorldId1;
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+
+        #[derive(Clone, PartialEq, Debug)]
+        struct Depth(isize);
+        component_manager.register_component::<Depth>(Box::new(DenseList::new()));
+        component_manager.register_clone::<Depth>();
+
+        let entities: Vec<_> = (0..3).map(|depth| {
+            let entity = entity_manager.create_entity();
+            component_manager.entity_created(&entity);
+            component_manager.assign_component(&entity, Depth(depth));
+            entity
+        }).collect();
+
+        assert!(component_manager.sort_components::<Depth, _>(|a, b| b.0.cmp(&a.0)));
+        let snapshot = component_manager.snapshot();
+        assert!(component_manager.sort_components::<Depth, _>(|a, b| a.0.cmp(&b.0)));
+
+        component_manager.restore(&snapshot);
+
+        let in_order: Vec<_> = component_manager.entities_in_order::<Depth>(&entity_manager).collect();
+        assert_eq!(in_order, vec![entities[2].clone(), entities[1].clone(), entities[0].clone()]);
+    }
+
+    #[test]
+    fn component_hooks() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
 
         #[derive(PartialEq, Debug)]
-        struct TupleComponent(isize);
-        component_manager.register_component::<TupleComponent>(Box::new(HashMap::new()));
+        struct Transform(isize);
+        #[derive(Clone)]
+        struct RigidBody(isize);
+        #[derive(PartialEq, Debug)]
+        struct Replaced(isize, isize);
+
+        impl<WorldId> ComponentHooks<WorldId> for RigidBody {
+            fn on_add(component_manager: &mut ComponentManager<WorldId>, entity: &Entity<WorldId>) {
+                if !component_manager.has_component::<Transform>(entity) {
+                    component_manager.assign_component(entity, Transform(0));
+                }
+            }
+
+            fn on_replace(component_manager: &mut ComponentManager<WorldId>, entity: &Entity<WorldId>, old: &RigidBody, new: &RigidBody) {
+                // already stored
+                assert_eq!(component_manager.get_component::<RigidBody>(entity).map(|body| body.0), Some(new.0));
+                component_manager.assign_component(entity, Replaced(old.0, new.0));
+            }
+
+            fn on_remove(component_manager: &mut ComponentManager<WorldId>, entity: &Entity<WorldId>) {
+                component_manager.remove_component::<Transform>(entity);
+            }
+        }
+
+        component_manager.register_component::<Transform>(Box::new(VecMap::new()));
+        component_manager.register_component::<RigidBody>(Box::new(VecMap::new()));
+        component_manager.register_component::<Replaced>(Box::new(VecMap::new()));
+        component_manager.register_hooks::<RigidBody>();
+
+        let entity = entity_manager.create_entity();
+        component_manager.entity_created(&entity);
+
+        component_manager.assign_component(&entity, RigidBody(1));
+        assert_eq!(component_manager.get_component::<Transform>(&entity), Some(&Transform(0)));
+        assert!(!component_manager.has_component::<Replaced>(&entity));
+
+        component_manager.assign_component(&entity, RigidBody(2));
+        assert_eq!(component_manager.get_component::<Replaced>(&entity), Some(&Replaced(1, 2)));
+        assert_eq!(component_manager.get_component::<RigidBody>(&entity).map(|body| body.0), Some(2));
+
+        component_manager.remove_component::<RigidBody>(&entity);
+        assert!(!component_manager.has_component::<Transform>(&entity));
+
+        component_manager.assign_component(&entity, RigidBody(3));
+        component_manager.entity_destroyed(&entity);
+        assert!(!component_manager.has_component::<Transform>(&entity));
+    }
+
+    #[test]
+    fn required_components() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+
+        #[derive(Default, PartialEq, Debug)]
+        struct Transform(isize);
+        struct Sprite;
+
+        component_manager.register_component::<Transform>(Box::new(VecMap::new()));
+        component_manager.register_component::<Sprite>(Box::new(VecMap::new()));
+        component_manager.register_required::<Sprite, Transform>(&entity_manager);
+
+        let entity1 = entity_manager.create_entity();
+        component_manager.entity_created(&entity1);
+        component_manager.assign_component(&entity1, Sprite);
+        assert_eq!(component_manager.get_component::<Transform>(&entity1), Some(&Transform(0)));
+
+        let entity2 = entity_manager.create_entity();
+        component_manager.entity_created(&entity2);
+        component_manager.assign_component(&entity2, Transform(1));
+        component_manager.assign_component(&entity2, Sprite);
+        assert_eq!(component_manager.get_component::<Transform>(&entity2), Some(&Transform(1)));
+
+        component_manager.remove_component::<Sprite>(&entity2);
+        component_manager.remove_component::<Transform>(&entity2);
+        assert!(!component_manager.has_component::<Transform>(&entity2));
+
+        component_manager.entity_destroyed(&entity1);
+        assert!(!component_manager.has_component::<Sprite>(&entity1));
+    }
+
+    #[test]
+    fn required_components_of_existing_entities() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+
+        #[derive(Default, PartialEq, Debug)]
+        struct Transform(isize);
+        struct Sprite;
+
+        component_manager.register_component::<Transform>(Box::new(VecMap::new()));
+        component_manager.register_component::<Sprite>(Box::new(VecMap::new()));
+
+        let entity1 = entity_manager.create_entity();
+        component_manager.entity_created(&entity1);
+        component_manager.assign_component(&entity1, Sprite);
+
+        let entity2 = entity_manager.create_entity();
+        component_manager.entity_created(&entity2);
+        component_manager.assign_component(&entity2, Sprite);
+        component_manager.assign_component(&entity2, Transform(1));
+
+        let entity3 = entity_manager.create_entity();
+        component_manager.entity_created(&entity3);
+
+        component_manager.register_required::<Sprite, Transform>(&entity_manager);
+        assert_eq!(component_manager.get_component::<Transform>(&entity1), Some(&Transform(0)));
+        assert_eq!(component_manager.get_component::<Transform>(&entity2), Some(&Transform(1)));
+        assert!(!component_manager.has_component::<Transform>(&entity3));
+        assert!(!component_manager.remove_component::<Transform>(&entity1));
+    }
+
+    #[test]
+    fn remove_required_component() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+
+        #[derive(Default)]
+        struct Transform;
+        struct Sprite;
+
+        component_manager.register_component::<Transform>(Box::new(VecMap::new()));
+        component_manager.register_component::<Sprite>(Box::new(VecMap::new()));
+        component_manager.register_required::<Sprite, Transform>(&entity_manager);
+
+        let entity = entity_manager.create_entity();
+        component_manager.entity_created(&entity);
+        component_manager.assign_component(&entity, Sprite);
+        assert!(!component_manager.remove_component::<Transform>(&entity));
+        assert!(component_manager.take_component::<Transform>(&entity).is_none());
+        assert!(component_manager.has_component::<Transform>(&entity));
+
+        // in either order
+        let transform = component_manager.get_component_id::<Transform>();
+        let sprite = component_manager.get_component_id::<Sprite>();
+        assert!(component_manager.remove_component_ids(&entity, &[transform, sprite]));
+        assert!(!component_manager.has_component::<Transform>(&entity));
+        assert!(!component_manager.has_component::<Sprite>(&entity));
+    }
+
+    #[test]
+    fn assign_bundle() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
 
         #[derive(PartialEq, Debug)]
-        struct Component {
-            field: isize,
+        struct Position(isize);
+        #[derive(PartialEq, Debug)]
+        struct Velocity(isize);
+
+        component_manager.register_component::<Position>(Box::new(VecMap::new()));
+        component_manager.register_component::<Velocity>(Box::new(VecMap::new()));
+
+        let entity = entity_manager.create_entity();
+        component_manager.entity_created(&entity);
+        component_manager.assign_component(&entity, Position(0));
+
+        component_manager.assign_bundle(&entity, (Position(1), Velocity(2)));
+        assert_eq!(component_manager.get_component::<Position>(&entity), Some(&Position(1)));
+        assert_eq!(component_manager.get_component::<Velocity>(&entity), Some(&Velocity(2)));
+        assert_eq!(component_manager.added::<Velocity>(&entity_manager, 0).count(), 1);
+    }
+
+    #[test]
+    #[should_fail]
+    fn assign_bundle_twice_the_same() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+
+        struct Position(isize);
+        component_manager.register_component::<Position>(Box::new(VecMap::new()));
+
+        let entity = entity_manager.create_entity();
+        component_manager.entity_created(&entity);
+        component_manager.assign_bundle(&entity, (Position(1), Position(2)));
+    }
+
+    #[test]
+    fn cached_queries() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+
+        struct Position;
+        struct Velocity;
+        struct Frozen;
+
+        component_manager.register_component::<Position>(Box::new(VecMap::new()));
+        component_manager.register_component::<Velocity>(Box::new(VecMap::new()));
+        component_manager.register_component::<Frozen>(Box::new(VecMap::new()));
+
+        let entity1 = entity_manager.create_entity();
+        component_manager.entity_created(&entity1);
+        component_manager.assign_component(&entity1, Position);
+        component_manager.assign_component(&entity1, Velocity);
+
+        let position = component_manager.get_component_id::<Position>();
+        let velocity = component_manager.get_component_id::<Velocity>();
+        let frozen = component_manager.get_component_id::<Frozen>();
+        let query_id = component_manager.register_query(&[position, velocity], &[frozen]);
+        assert_eq!(component_manager.register_query(&[velocity, position], &[frozen]), query_id);
+        assert_eq!(component_manager.query(query_id).to_vec(), vec![entity1.index()]);
+
+        let entity2 = entity_manager.create_entity();
+        component_manager.entity_created(&entity2);
+        component_manager.assign_component(&entity2, Position);
+        assert_eq!(component_manager.query(query_id).len(), 1);
+        component_manager.assign_component(&entity2, Velocity);
+        assert_eq!(component_manager.query_entities(&entity_manager, query_id).count(), 2);
+
+        component_manager.assign_component(&entity1, Frozen);
+        assert_eq!(component_manager.query_entities(&entity_manager, query_id).collect::<Vec<_>>(), vec![entity2.clone()]);
+
+        component_manager.entity_destroyed(&entity2);
+        assert!(component_manager.query(query_id).is_empty());
+    }
+
+    #[test]
+    fn find_by_value() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+
+        #[derive(PartialEq)]
+        struct Player(usize);
+
+        component_manager.register_component::<Player>(Box::new(VecMap::new()));
+
+        let entities: Vec<_> = (0..3).map(|i| {
+            let entity = entity_manager.create_entity();
+            component_manager.entity_created(&entity);
+            component_manager.assign_component(&entity, Player(i));
+            entity
+        }).collect();
+
+        let found: Vec<usize> = component_manager.find(&entity_manager, &Player(1)).map(|entity| entity.index()).collect();
+        assert_eq!(found, vec![1]);
+        assert_eq!(component_manager.find(&entity_manager, &Player(3)).count(), 0);
+
+        component_manager.entity_destroyed(&entities[1]);
+        entity_manager.destroy_entity(entities[1].clone());
+        component_manager.remove_component::<Player>(&entities[2]);
+        assert_eq!(component_manager.find(&entity_manager, &Player(1)).count(), 0);
+        assert_eq!(component_manager.find(&entity_manager, &Player(2)).count(), 0);
+        assert_eq!(component_manager.find(&entity_manager, &Player(0)).collect::<Vec<_>>(), vec![entities[0].clone()]);
+    }
+
+    #[test]
+    fn secondary_index() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+
+        struct Player(u32);
+
+        fn player_id(player: &Player) -> u32 {
+            player.0
         }
-        component_manager.register_component::<Component>(Box::new(VecMap::new()));
+
+        component_manager.register_component::<Player>(Box::new(VecMap::new()));
+
+        let entity1 = entity_manager.create_entity();
+        component_manager.entity_created(&entity1);
+        component_manager.assign_component(&entity1, Player(1));
+
+        component_manager.register_index::<Player, u32>(player_id);
+        assert_eq!(component_manager.find_by::<Player, u32>(&entity_manager, &1).collect::<Vec<_>>(), vec![entity1.clone()]);
+
+        let entity2 = entity_manager.create_entity();
+        component_manager.entity_created(&entity2);
+        component_manager.assign_component(&entity2, Player(2));
+        assert_eq!(component_manager.find_by::<Player, u32>(&entity_manager, &2).collect::<Vec<_>>(), vec![entity2.clone()]);
+
+        // changing it every frame without lookups only remembers the entity once
+        for _ in 0..100 {
+            component_manager.get_component_mut::<Player>(&entity2).unwrap().0 = 1;
+        }
+        assert_eq!(component_manager.get_component_data::<Player>().dirty_indexes.borrow().len(), 1);
+        assert_eq!(component_manager.find_by::<Player, u32>(&entity_manager, &1).count(), 2);
+        assert_eq!(component_manager.find_by::<Player, u32>(&entity_manager, &2).count(), 0);
+
+        component_manager.remove_component::<Player>(&entity1);
+        assert_eq!(component_manager.find_by::<Player, u32>(&entity_manager, &1).collect::<Vec<_>>(), vec![entity2.clone()]);
+    }
+
+    #[test]
+    fn spatial_index() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+
+        struct Position(f32, f32);
+
+        impl Spatial for Position {
+            fn position(&self) -> (f32, f32) {
+                (self.0, self.1)
+            }
+        }
+
+        component_manager.register_component::<Position>(Box::new(VecMap::new()));
+        component_manager.register_spatial_index::<Position>(10.0);
 
         let entity = entity_manager.create_entity();
         component_manager.entity_created(&entity);
+        component_manager.assign_component(&entity, Position(50.0, 50.0));
+        assert_eq!(component_manager.find_within_radius::<Position>(&entity_manager, (0.0, 0.0), 5.0).count(), 0);
 
-        component_manager.assign_component::<UnitComponent>(&entity, UnitComponent);
-        component_manager.assign_component::<TupleComponent>(&entity, TupleComponent(1));
-        component_manager.assign_component::<Component>(&entity, Component { field: 1 });
+        component_manager.get_component_mut::<Position>(&entity).unwrap().0 = 1.0;
+        component_manager.get_component_mut::<Position>(&entity).unwrap().1 = 1.0;
+        assert_eq!(component_manager.find_within_radius::<Position>(&entity_manager, (0.0, 0.0), 5.0).collect::<Vec<_>>(), vec![entity.clone()]);
+        assert_eq!(component_manager.find_within_aabb::<Position>(&entity_manager, (0.0, 0.0), (2.0, 2.0)).count(), 1);
 
-        // test assigned components
-        let unit_component = component_manager.get_component::<UnitComponent>(&entity);
-        assert_eq!(unit_component.unwrap(), &UnitComponent);
+        component_manager.remove_component::<Position>(&entity);
+        assert_eq!(component_manager.find_within_aabb::<Position>(&entity_manager, (0.0, 0.0), (2.0, 2.0)).count(), 0);
+    }
 
-        let tuple_component = component_manager.get_component::<TupleComponent>(&entity);
-        assert_eq!(tuple_component.unwrap(), &TupleComponent(1));
+    #[test]
+    fn par_for_each() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
 
-        let component = component_manager.get_component::<Component>(&entity);
-        assert_eq!(component.unwrap(), &Component { field: 1 });
+        struct Counter(usize);
+        struct Step(usize);
+        struct Frozen;
+
+        component_manager.register_component::<Counter>(Box::new(VecMap::new()));
+        component_manager.register_component::<Step>(Box::new(VecMap::new()));
+        component_manager.register_component::<Frozen>(Box::new(VecMap::new()));
+
+        let entities: Vec<_> = (0..1000).map(|i| {
+            let entity = entity_manager.create_entity();
+            component_manager.entity_created(&entity);
+            component_manager.assign_component(&entity, Counter(i));
+            if i % 3 == 0 {
+                component_manager.assign_component(&entity, Step(10));
+            }
+            if i % 2 == 0 {
+                component_manager.assign_component(&entity, Frozen);
+            }
+            entity
+        }).collect();
+
+        let counter = component_manager.get_component_id::<Counter>();
+        let frozen = component_manager.get_component_id::<Frozen>();
+        let query_id = component_manager.register_query(&[counter], &[frozen]);
+
+        let tick = component_manager.tick();
+        component_manager.increment_tick();
+
+        let mut thread_pool = ThreadPool::new();
+        component_manager.par_for_each::<(&mut Counter, Option<&Step>), _>(&entity_manager, &mut thread_pool, query_id, 4, |entity, (counter, step)| {
+            counter.0 += entity.index() + step.map_or(1, |step| step.0);
+        });
+
+        for (i, entity) in entities.iter().enumerate() {
+            let expected = match i % 3 {
+                _ if i % 2 == 0 => i,
+                0 => 2 * i + 10,
+                _ => 2 * i + 1,
+            };
+            assert_eq!(component_manager.get_component::<Counter>(entity).unwrap().0, expected);
+            assert_eq!(component_manager.is_changed_since::<Counter>(entity, tick), i % 2 == 1);
+        }
+    }
+
+    #[test]
+    #[should_fail]
+    fn par_for_each_borrows_twice() {
+        struct WorldId1;
+        let entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+
+        struct Counter(usize);
+
+        component_manager.register_component::<Counter>(Box::new(VecMap::new()));
+
+        let counter = component_manager.get_component_id::<Counter>();
+        let query_id = component_manager.register_query(&[counter], &[]);
+
+        let mut thread_pool = ThreadPool::new();
+        component_manager.par_for_each::<(&mut Counter, &Counter), _>(&entity_manager, &mut thread_pool, query_id, 4, |_, _| {});
+    }
+
+    #[test]
+    fn sort_components() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+
+        #[derive(PartialEq, Debug)]
+        struct Depth(isize);
+        struct Unsorted(isize);
+
+        component_manager.register_component::<Depth>(Box::new(DenseList::new()));
+        component_manager.register_component::<Unsorted>(Box::new(VecMap::new()));
+
+        let entities: Vec<_> = [3, 1, 2].iter().map(|&depth| {
+            let entity = entity_manager.create_entity();
+            component_manager.entity_created(&entity);
+            component_manager.assign_component(&entity, Depth(depth));
+            component_manager.assign_component(&entity, Unsorted(depth));
+            entity
+        }).collect();
+
+        assert!(component_manager.sort_components::<Depth, _>(|a, b| a.0.cmp(&b.0)));
+        let ordered: Vec<_> = component_manager.entities_in_order::<Depth>(&entity_manager).collect();
+        assert_eq!(ordered, vec![entities[1].clone(), entities[2].clone(), entities[0].clone()]);
+        assert_eq!(component_manager.get_component::<Depth>(&entities[0]), Some(&Depth(3)));
+
+        assert!(!component_manager.sort_components::<Unsorted, _>(|a, b| a.0.cmp(&b.0)));
+        let sorted: Vec<_> = component_manager.sorted::<Unsorted, _>(&entity_manager, |a, b| b.0.cmp(&a.0)).collect();
+        assert_eq!(sorted, vec![entities[0].clone(), entities[2].clone(), entities[1].clone()]);
     }
 
     #[test]
@@ -1,6 +1,8 @@
 use std::marker::PhantomData;
 
 use entity::{ EntityManager, Entity };
+use component::{ ComponentManager };
+use names::{ EntityNames };
 
 pub trait EntityBuilder<WorldId, S>: 'static {
     fn build(&mut self, &mut EntityManager<WorldId>, &mut S, Entity<WorldId>);
@@ -27,18 +29,25 @@ pub struct Control<WorldId, S> {
     builders: Vec<Box<EntityBuilder<WorldId, S> + 'static>>,
     destroyed: Vec<Entity<WorldId>>,
     modifiers: Vec<(Entity<WorldId>, Box<EntityModifier<WorldId, S> + 'static>)>,
+    last_run: usize,
 }
 
 impl<WorldId, S> Control<WorldId, S> {
-    pub fn new() -> Control<WorldId, S> {
+    pub fn new(last_run: usize) -> Control<WorldId, S> {
         Control {
             phantom: PhantomData,
             builders: Vec::new(),
             destroyed: Vec::new(),
             modifiers: Vec::new(),
+            last_run: last_run,
         }
     }
 
+    /// Tick of the previous update of the system, for use with ComponentManager change detection
+    pub fn last_run(&self) -> usize {
+        self.last_run
+    }
+
     pub fn build(&mut self, builder: Box<EntityBuilder<WorldId, S> + 'static>) {
         self.builders.push(builder);
     }
@@ -51,10 +60,11 @@ impl<WorldId, S> Control<WorldId, S> {
         self.modifiers.push((entity, modifier));
     }
 
-    pub fn apply(self, entity_manager: &mut EntityManager<WorldId>, system: &mut S) {
+    pub fn apply(self, entity_manager: &mut EntityManager<WorldId>, component_manager: &mut ComponentManager<WorldId>, entity_names: &mut EntityNames<WorldId>, system: &mut S) {
         let mut entity_manager = entity_manager;
         for mut builder in self.builders.into_iter() {
             let entity = entity_manager.create_entity();
+            component_manager.entity_created(&entity);
             builder.build(entity_manager, system, entity);
         }
 
@@ -63,6 +73,8 @@ impl<WorldId, S> Control<WorldId, S> {
         }
 
         for entity in self.destroyed.into_iter() {
+            component_manager.entity_destroyed(&entity);
+            entity_names.entity_destroyed(&entity);
             entity_manager.destroy_entity(entity);
         }
     }
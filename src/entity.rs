@@ -1,10 +1,10 @@
 use std::marker::PhantomData;
-use std::collections::{ VecDeque };
-use std::collections::vec_deque::{ Iter };
+use std::collections::{ BitVec, VecDeque, VecMap };
 
 use std::iter::{ Iterator, repeat };
 use std::{ usize };
 use std::fmt::{ Debug, Formatter, Error };
+use std::hash::{ Hash, Hasher };
 
 // TODO get rid of usize here
 // use 1/4 of usize bits for version rest for index
@@ -62,6 +62,12 @@ impl<WorldId> Clone for Entity<WorldId> {
     }
 }
 
+impl<WorldId> Hash for Entity<WorldId> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state)
+    }
+}
+
 impl<WorldId> Debug for Entity<WorldId> {
     fn fmt(&self, formatter: &mut Formatter) -> Result<(), Error> {
         self.id.fmt(formatter)
@@ -74,10 +80,24 @@ pub struct EntityManager<WorldId> {
 
     // FIFO
     free_entity_index_list: VecDeque<usize>,
+    // the same indices by index, so iterating doesn't need to sort the list
+    free_entity_indices: BitVec,
 
     entity_versions: Vec<usize>,
 }
 
+impl<WorldId> Clone for EntityManager<WorldId> {
+    fn clone(&self) -> Self {
+        EntityManager {
+            phantom: PhantomData,
+            next_entity_index: self.next_entity_index,
+            free_entity_index_list: self.free_entity_index_list.clone(),
+            free_entity_indices: self.free_entity_indices.clone(),
+            entity_versions: self.entity_versions.clone(),
+        }
+    }
+}
+
 impl<'a, WorldId> EntityManager<WorldId> {
     pub fn new(initial_capacity: usize) -> EntityManager<WorldId> {
 
@@ -85,6 +105,7 @@ impl<'a, WorldId> EntityManager<WorldId> {
             phantom: PhantomData,
             next_entity_index: 0,
             free_entity_index_list: VecDeque::with_capacity(MINIMUM_FREE_ENTITY_INDICES),
+            free_entity_indices: BitVec::from_elem(initial_capacity, false),
 
             entity_versions: repeat(0usize).take(initial_capacity).collect(),
         }
@@ -93,7 +114,9 @@ impl<'a, WorldId> EntityManager<WorldId> {
     pub fn create_entity(&mut self) -> Entity<WorldId> {
         let index = if self.free_entity_index_list.len() > MINIMUM_FREE_ENTITY_INDICES {
             // FIFO
-            self.free_entity_index_list.pop_front().unwrap()
+            let index = self.free_entity_index_list.pop_front().unwrap();
+            self.free_entity_indices.set(index, false);
+            index
         } else {
             let index = self.next_entity_index;
             self.next_entity_index += 1;
@@ -115,6 +138,12 @@ impl<'a, WorldId> EntityManager<WorldId> {
         self.entity_versions[entity.index()] += 1;
         // FIFO
         self.free_entity_index_list.push_back(entity.index());
+
+        let free_entity_indices_len = self.free_entity_indices.len();
+        if entity.index() >= free_entity_indices_len {
+            self.free_entity_indices.grow(self.entity_versions.len() - free_entity_indices_len, false);
+        }
+        self.free_entity_indices.set(entity.index(), true);
     }
 
     pub fn is_valid(&self, entity: &Entity<WorldId>) -> bool {
@@ -122,13 +151,17 @@ impl<'a, WorldId> EntityManager<WorldId> {
         && entity.version() == self.entity_versions[entity.index()]
     }
 
+    /// Entity currently using the index, only meaningful if the index isn't free
+    pub fn get_entity(&self, index: usize) -> Entity<WorldId> {
+        Entity::new(index, self.entity_versions[index])
+    }
+
     pub fn entities(&self) -> EntityIterator<WorldId> {
         EntityIterator {
             phantom: PhantomData,
             entity_manager: self,
-            last_entity_index: self.next_entity_index - 1, // last valid entity index
+            next_entity_index: self.next_entity_index,
             index: 0,
-            free_entity_index_list: self.free_entity_index_list.iter(),
         }
     }
 }
@@ -136,9 +169,8 @@ impl<'a, WorldId> EntityManager<WorldId> {
 pub struct EntityIterator<'a, WorldId: 'a> {
     phantom: PhantomData<WorldId>,
     entity_manager: &'a EntityManager<WorldId>,
-    last_entity_index: usize,
+    next_entity_index: usize,
     index: usize,
-    free_entity_index_list: Iter<'a, usize>,
 }
 
 impl<'a, WorldId> Iterator for EntityIterator<'a, WorldId> {
@@ -146,34 +178,64 @@ impl<'a, WorldId> Iterator for EntityIterator<'a, WorldId> {
 
     fn next(&mut self) -> Option<Entity<WorldId>> {
         // for all valid entity indexes
-        while self.index <= self.last_entity_index {
-            let mut free_entity_index = -1;
-
-            // find if the index is in the free_entity_index_list
-            while free_entity_index < self.index {
-                free_entity_index = match self.free_entity_index_list.next() {
-                    Some(x) => *x,
-                    None => usize::MAX,
-                }
-            }
+        while self.index < self.next_entity_index {
+            let index = self.index;
+            self.index += 1;
 
-            if free_entity_index == self.index {
-                self.index += 1;
+            if self.entity_manager.free_entity_indices.get(index).unwrap_or(false) {
                 continue;
             }
 
-            let version = self.entity_manager.entity_versions[self.index];
+            let version = self.entity_manager.entity_versions[index];
 
-            let result = Some(Entity::new(self.index, version));
-
-            self.index += 1;
-            return result;
+            return Some(Entity::new(index, version));
         }
 
         None::<Entity<WorldId>>
     }
 }
 
+/// Maps entities of one World to the entities they became in another
+pub struct EntityMap<From, To> {
+    phantom: PhantomData<From>,
+    // by source index: source version and target entity
+    entities: VecMap<(usize, Entity<To>)>,
+}
+
+impl<From, To> EntityMap<From, To> {
+    pub fn new() -> EntityMap<From, To> {
+        EntityMap {
+            phantom: PhantomData,
+            entities: VecMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, from: &Entity<From>, to: Entity<To>) {
+        self.entities.insert(from.index(), (from.version(), to));
+    }
+
+    /// None for entities that weren't moved
+    pub fn get(&self, from: &Entity<From>) -> Option<Entity<To>> {
+        match self.entities.get(&from.index()) {
+            Some(&(version, ref to)) if version == from.version() => Some(to.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+}
+
+/// Implemented by components with Entity fields, to convert them when moved to another World
+/// The EntityMap only contains the moved entities, all of them for World::merge but just the one for World::move_entity_to
+/// Entities staying behind have no counterpart in the other World, so map_entities must handle get returning None
+pub trait MapEntities<From, To> {
+    type Output: 'static;
+
+    fn map_entities(self, entity_map: &EntityMap<From, To>) -> Self::Output;
+}
+
 #[cfg(test)]
 mod tests {
     use test::Bencher;
@@ -202,6 +264,42 @@ mod tests {
         assert!(!entity_manager.is_valid(&entity1_clone));
     }
 
+    #[test]
+    fn entities_skips_destroyed_entities() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+
+        assert_eq!(entity_manager.entities().count(), 0);
+
+        let entity1 = entity_manager.create_entity();
+        let entity2 = entity_manager.create_entity();
+        let entity3 = entity_manager.create_entity();
+        entity_manager.destroy_entity(entity3);
+        entity_manager.destroy_entity(entity1);
+
+        let entities: Vec<_> = entity_manager.entities().collect();
+        assert_eq!(entities, vec![entity2]);
+    }
+
+    #[test]
+    fn entities_includes_reused_indices() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(16);
+
+        let entities: Vec<_> = (0..super::MINIMUM_FREE_ENTITY_INDICES + 2).map(|_| entity_manager.create_entity()).collect();
+        for entity in entities.into_iter() {
+            entity_manager.destroy_entity(entity);
+        }
+        assert_eq!(entity_manager.entities().count(), 0);
+
+        // more than MINIMUM_FREE_ENTITY_INDICES are free, so the first destroyed index is reused
+        let entity = entity_manager.create_entity();
+        assert_eq!(entity.index(), 0);
+
+        let entities: Vec<_> = entity_manager.entities().collect();
+        assert_eq!(entities, vec![entity]);
+    }
+
     #[bench]
     fn create_1mm_entities(bencher: &mut Bencher) {
 
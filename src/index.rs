@@ -0,0 +1,276 @@
+use std::any::{ Any };
+use std::marker::PhantomData;
+use std::collections::{ HashMap, VecMap };
+use std::hash::{ Hash };
+use std::{ i32 };
+
+/// Secondary index over the values of a component, kept up to date by the ComponentManager
+/// Changes through get_component_mut are applied before the next lookup
+pub trait ComponentIndex<C> {
+    /// The component of the entity was added or changed, after removing its previous value
+    fn insert(&mut self, entity_index: usize, component: &C);
+
+    /// Does nothing if the entity isn't indexed
+    fn remove(&mut self, entity_index: usize);
+
+    fn as_any(&self) -> &Any;
+}
+
+/// Entity indices by a key derived from their component, e.g. Player(id) -> id
+pub struct ValueIndex<C, K> {
+    key: fn(&C) -> K,
+    entity_indices: HashMap<K, Vec<usize>>,
+    keys: VecMap<K>,
+}
+
+impl<C, K: Hash + Eq + Clone> ValueIndex<C, K> {
+    pub fn new(key: fn(&C) -> K) -> ValueIndex<C, K> {
+        ValueIndex {
+            key: key,
+            entity_indices: HashMap::new(),
+            keys: VecMap::new(),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> &[usize] {
+        match self.entity_indices.get(key) {
+            Some(entity_indices) => &entity_indices[..],
+            None => &[],
+        }
+    }
+}
+
+impl<C: 'static, K: Hash + Eq + Clone + 'static> ComponentIndex<C> for ValueIndex<C, K> {
+    fn insert(&mut self, entity_index: usize, component: &C) {
+        let key = (self.key)(component);
+        self.entity_indices.entry(key.clone()).or_insert(Vec::new()).push(entity_index);
+        self.keys.insert(entity_index, key);
+    }
+
+    fn remove(&mut self, entity_index: usize) {
+        let key = match self.keys.remove(&entity_index) {
+            Some(key) => key,
+            None => return,
+        };
+
+        let empty = {
+            let entity_indices = self.entity_indices.get_mut(&key).unwrap();
+            entity_indices.retain(|&index| index != entity_index);
+            entity_indices.is_empty()
+        };
+
+        if empty {
+            self.entity_indices.remove(&key);
+        }
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+/// Component with a 2D position, for SpatialGrid
+pub trait Spatial {
+    fn position(&self) -> (f32, f32);
+}
+
+/// Uniform grid of entity indices by the position of their component
+/// Cells should be about the size of typical search radiuses
+pub struct SpatialGrid<C> {
+    phantom: PhantomData<C>,
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    positions: VecMap<(f32, f32)>,
+}
+
+impl<C> SpatialGrid<C> {
+    pub fn new(cell_size: f32) -> SpatialGrid<C> {
+        assert!(cell_size > 0.0, "Tried to create spatial grid with empty cells");
+
+        SpatialGrid {
+            phantom: PhantomData,
+            cell_size: cell_size,
+            cells: HashMap::new(),
+            positions: VecMap::new(),
+        }
+    }
+
+    fn cell(&self, (x, y): (f32, f32)) -> (i32, i32) {
+        (cell_coordinate(x / self.cell_size), cell_coordinate(y / self.cell_size))
+    }
+
+    /// Entity indices with a position inside the box, including its edges
+    /// Bounds may be infinite, a box with NaN bounds contains nothing
+    pub fn within_aabb(&self, min: (f32, f32), max: (f32, f32)) -> Vec<usize> {
+        if !(min.0 <= max.0 && min.1 <= max.1) {
+            return Vec::new();
+        }
+
+        let (min_x, min_y) = self.cell(min);
+        let (max_x, max_y) = self.cell(max);
+
+        let mut entity_indices = Vec::new();
+        {
+            let mut add_cell = |cell: &Vec<usize>| {
+                entity_indices.extend(cell.iter()
+                    .map(|&entity_index| entity_index)
+                    .filter(|entity_index| in_aabb(self.positions[*entity_index], min, max)));
+            };
+
+            // as f64, the box can span the whole i32 range in both directions
+            let cell_count = (max_x as f64 - min_x as f64 + 1.0) * (max_y as f64 - min_y as f64 + 1.0);
+
+            if cell_count > self.cells.len() as f64 {
+                // fewer occupied cells than cells in the box
+                for (&(cell_x, cell_y), cell) in self.cells.iter() {
+                    if cell_x >= min_x && cell_x <= max_x && cell_y >= min_y && cell_y <= max_y {
+                        add_cell(cell);
+                    }
+                }
+            } else {
+                for cell_x in min_x as i64..max_x as i64 + 1 {
+                    for cell_y in min_y as i64..max_y as i64 + 1 {
+                        if let Some(cell) = self.cells.get(&(cell_x as i32, cell_y as i32)) {
+                            add_cell(cell);
+                        }
+                    }
+                }
+            }
+        }
+
+        entity_indices
+    }
+
+    /// Entity indices with a position at most radius away from center
+    pub fn within_radius(&self, center: (f32, f32), radius: f32) -> Vec<usize> {
+        let mut entity_indices = self.within_aabb((center.0 - radius, center.1 - radius), (center.0 + radius, center.1 + radius));
+        entity_indices.retain(|entity_index| {
+            let (x, y) = self.positions[*entity_index];
+            (x - center.0) * (x - center.0) + (y - center.1) * (y - center.1) <= radius * radius
+        });
+        entity_indices
+    }
+}
+
+// floor clamped to the i32 range, so infinite positions don't overflow, NaN goes to cell 0
+fn cell_coordinate(value: f32) -> i32 {
+    if value.is_nan() {
+        0
+    } else if value <= i32::MIN as f32 {
+        i32::MIN
+    } else if value >= i32::MAX as f32 {
+        i32::MAX
+    } else {
+        value.floor() as i32
+    }
+}
+
+fn in_aabb((x, y): (f32, f32), min: (f32, f32), max: (f32, f32)) -> bool {
+    x >= min.0 && x <= max.0 && y >= min.1 && y <= max.1
+}
+
+impl<C: Spatial + 'static> ComponentIndex<C> for SpatialGrid<C> {
+    fn insert(&mut self, entity_index: usize, component: &C) {
+        let position = component.position();
+        let cell = self.cell(position);
+        self.cells.entry(cell).or_insert(Vec::new()).push(entity_index);
+        self.positions.insert(entity_index, position);
+    }
+
+    fn remove(&mut self, entity_index: usize) {
+        let position = match self.positions.remove(&entity_index) {
+            Some(position) => position,
+            None => return,
+        };
+
+        let cell = self.cell(position);
+        let empty = {
+            let entity_indices = self.cells.get_mut(&cell).unwrap();
+            entity_indices.retain(|&index| index != entity_index);
+            entity_indices.is_empty()
+        };
+
+        if empty {
+            self.cells.remove(&cell);
+        }
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ ComponentIndex, ValueIndex, Spatial, SpatialGrid };
+
+    #[test]
+    fn value_index() {
+        struct Player(u32);
+
+        fn player_id(player: &Player) -> u32 {
+            player.0
+        }
+
+        let mut index: ValueIndex<Player, u32> = ValueIndex::new(player_id);
+        index.insert(0, &Player(1));
+        index.insert(1, &Player(1));
+        index.insert(2, &Player(2));
+        assert_eq!(index.get(&1).to_vec(), vec![0, 1]);
+
+        index.remove(0);
+        index.remove(0);
+        assert_eq!(index.get(&1).to_vec(), vec![1]);
+        assert!(index.get(&3).is_empty());
+    }
+
+    #[test]
+    fn spatial_grid() {
+        struct Position(f32, f32);
+
+        impl Spatial for Position {
+            fn position(&self) -> (f32, f32) {
+                (self.0, self.1)
+            }
+        }
+
+        let mut grid: SpatialGrid<Position> = SpatialGrid::new(10.0);
+        grid.insert(0, &Position(0.0, 0.0));
+        grid.insert(1, &Position(-5.0, 3.0));
+        grid.insert(2, &Position(25.0, 25.0));
+
+        let mut near = grid.within_radius((0.0, 0.0), 6.0);
+        near.sort();
+        assert_eq!(near, vec![0, 1]);
+        assert_eq!(grid.within_aabb((20.0, 20.0), (30.0, 30.0)), vec![2]);
+
+        grid.remove(2);
+        grid.insert(2, &Position(1.0, 1.0));
+        assert_eq!(grid.within_radius((0.0, 0.0), 6.0).len(), 3);
+        assert!(grid.within_aabb((20.0, 20.0), (30.0, 30.0)).is_empty());
+    }
+
+    #[test]
+    fn spatial_grid_huge_bounds() {
+        use std::f32;
+
+        struct Position(f32, f32);
+
+        impl Spatial for Position {
+            fn position(&self) -> (f32, f32) {
+                (self.0, self.1)
+            }
+        }
+
+        let mut grid: SpatialGrid<Position> = SpatialGrid::new(1.0);
+        grid.insert(0, &Position(0.0, 0.0));
+        grid.insert(1, &Position(1.0e20, -1.0e20));
+        grid.insert(2, &Position(f32::INFINITY, 0.0));
+
+        assert_eq!(grid.within_radius((0.0, 0.0), 1.0e9), vec![0]);
+        assert_eq!(grid.within_aabb((f32::NEG_INFINITY, f32::NEG_INFINITY), (f32::INFINITY, f32::INFINITY)).len(), 3);
+        assert_eq!(grid.within_radius((0.0, 0.0), f32::INFINITY).len(), 3);
+        assert!(grid.within_radius((f32::NAN, 0.0), 1.0).is_empty());
+        assert!(grid.within_aabb((0.0, 0.0), (f32::NAN, 1.0)).is_empty());
+    }
+}
\ No newline at end of file
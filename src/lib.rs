@@ -1,22 +1,41 @@
-#![feature(test,collections,core)]
+#![feature(test,collections,core,std_misc)]
 
 extern crate anymap;
+extern crate bincode;
+extern crate rustc_serialize;
 extern crate test;
 
-pub use world::World;
-pub use entity::{ EntityManager, Entity };
+pub use world::{ World, WorldSnapshot };
+pub use entity::{ EntityManager, Entity, EntityMap, MapEntities };
 pub use system::{ System, SystemManager };
 pub use control::{ Control };
-pub use component::{ ComponentManager, ComponentList, ComponentData };
+pub use component::{ Bundle, ComponentManager, ComponentList, ComponentData, DenseList, ComponentHooks, ComponentId, ComponentInfo, QueryId, StorageKind };
+pub use names::{ EntityNames };
+pub use index::{ ComponentIndex, ValueIndex, Spatial, SpatialGrid };
+pub use query::{ Filter, FilterList, FilterExpr, Fetch, ParFetch, ComponentPtr, ComponentMutPtr, FilterIterator, With, Without, Or, Relation, Join };
+pub use brand::{ Brand, scope };
+pub use runtime::{ Runtime, RuntimeWorld, RuntimeEntity };
+pub use replication::{ ReplicationServer, ReplicationClient };
+pub use thread_pool::{ ThreadPool };
 
-pub use tup_append::TupAppend;
+pub use tup_append::{ TupAppend, TupPrepend, TupConcat };
 
-mod world;
+#[macro_use]
 mod tup_append;
+#[macro_use]
+mod bytes;
+mod world;
 mod system;
 mod entity;
 mod control;
 mod component;
+mod query;
+mod index;
+mod names;
+mod brand;
+mod runtime;
+mod replication;
+mod thread_pool;
 
 #[cfg(test)]
 mod tests {
@@ -28,6 +47,7 @@ mod tests {
         EntityManager,
         ComponentManager,
         Control,
+        QueryId,
         System,
         TupAppend, // required for components macro
     };
@@ -77,6 +97,87 @@ mod tests {
         });
     }
 
+    #[bench]
+    fn bench_cached_query_over_100k_entities_with_5_components(bencher: &mut Bencher) {
+        let mut rng = XorShiftRng::new_unseeded();
+
+        let mut world: World<WorldId1> = World::new();
+
+        world.register_component::<Cmp1>(Box::new(VecMap::new()));
+        world.register_component::<Cmp2>(Box::new(VecMap::new()));
+        world.register_component::<Cmp3>(Box::new(VecMap::new()));
+        world.register_component::<Cmp4>(Box::new(VecMap::new()));
+        world.register_component::<Cmp5>(Box::new(HashMap::new()));
+
+        let with = [world.get_component_id::<Cmp2>(), world.get_component_id::<Cmp3>(), world.get_component_id::<Cmp4>(), world.get_component_id::<Cmp5>()];
+        let without = [world.get_component_id::<Cmp1>()];
+        let query_id = world.register_query(&with, &without);
+        world.register_system(CachedSys(query_id));
+
+        for _ in range(0usize, 100000usize) {
+            let entity = world.create_entity();
+            if rng.gen::<f32>() > 0.5f32 {
+                world.assign_component(&entity, Cmp1);
+            }
+            if rng.gen::<f32>() > 0.3f32 {
+                world.assign_component(&entity, Cmp2);
+            }
+            if rng.gen::<f32>() > 0.1f32 {
+                world.assign_component(&entity, Cmp3);
+            }
+            if rng.gen::<f32>() > 0.1f32 {
+                world.assign_component(&entity, Cmp4);
+            }
+            if rng.gen::<f32>() > 0.1f32 {
+                world.assign_component(&entity, Cmp5);
+            }
+        }
+
+        bencher.iter(|| {
+            world.update_system::<usize, CachedSys>(&0usize);
+        });
+    }
+
+    #[bench]
+    fn bench_par_for_each_over_100k_entities_with_5_components(bencher: &mut Bencher) {
+        let mut rng = XorShiftRng::new_unseeded();
+
+        let mut world: World<WorldId1> = World::new();
+
+        world.register_component::<Cmp1>(Box::new(VecMap::new()));
+        world.register_component::<Cmp2>(Box::new(VecMap::new()));
+        world.register_component::<Cmp3>(Box::new(VecMap::new()));
+        world.register_component::<Cmp4>(Box::new(VecMap::new()));
+        world.register_component::<Cmp5>(Box::new(HashMap::new()));
+
+        let with = [world.get_component_id::<Cmp2>(), world.get_component_id::<Cmp3>(), world.get_component_id::<Cmp4>(), world.get_component_id::<Cmp5>()];
+        let without = [world.get_component_id::<Cmp1>()];
+        let query_id = world.register_query(&with, &without);
+
+        for _ in range(0usize, 100000usize) {
+            let entity = world.create_entity();
+            if rng.gen::<f32>() > 0.5f32 {
+                world.assign_component(&entity, Cmp1);
+            }
+            if rng.gen::<f32>() > 0.3f32 {
+                world.assign_component(&entity, Cmp2);
+            }
+            if rng.gen::<f32>() > 0.1f32 {
+                world.assign_component(&entity, Cmp3);
+            }
+            if rng.gen::<f32>() > 0.1f32 {
+                world.assign_component(&entity, Cmp4);
+            }
+            if rng.gen::<f32>() > 0.1f32 {
+                world.assign_component(&entity, Cmp5);
+            }
+        }
+
+        bencher.iter(|| {
+            world.par_for_each::<(&mut Cmp2, &Cmp3, &Cmp4, &Cmp5), _>(query_id, 4, |_, _| {});
+        });
+    }
+
     struct WorldId1;
 
     #[derive(Debug)]
@@ -137,4 +238,25 @@ mod tests {
             }
         }
     }
+
+    struct CachedSys(QueryId);
+
+    impl<WorldId> System<WorldId, CachedSys> for CachedSys {
+        fn update<A>(&mut self, _: &EntityManager<WorldId>, component_manager: &ComponentManager<WorldId>, _: &mut Control<WorldId, CachedSys>, _: &A) {
+            let component_data = (component_manager.get_component_data::<Cmp2>(),)
+            .tup_append(component_manager.get_component_data::<Cmp3>())
+            .tup_append(component_manager.get_component_data::<Cmp4>())
+            .tup_append(component_manager.get_component_data::<Cmp5>());
+
+            let mut counter = 0usize;
+
+            for index in component_manager.query(self.0).iter() {
+                let _ = (component_data.0.list.get(index).unwrap(),
+                    component_data.1.list.get(index).unwrap(),
+                    component_data.2.list.get(index).unwrap(),
+                    component_data.3.list.get(index).unwrap());
+                counter += 1;
+            }
+        }
+    }
 }
\ No newline at end of file
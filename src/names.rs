@@ -0,0 +1,129 @@
+use std::marker::PhantomData;
+use std::collections::{ HashMap, VecMap };
+use std::collections::hash_map::{ Iter };
+
+use entity::{ Entity };
+
+// Entity ids are recycled, so they can't be used as keys outside of a running World
+// EntityNames maps a persistent name to an entity in both directions
+// Names are only removed explicitly or when their entity is destroyed
+pub struct EntityNames<WorldId> {
+    phantom: PhantomData<WorldId>,
+    entities: HashMap<String, Entity<WorldId>>,
+    names: VecMap<String>,
+}
+
+impl<WorldId> Clone for EntityNames<WorldId> {
+    fn clone(&self) -> Self {
+        EntityNames {
+            phantom: PhantomData,
+            entities: self.entities.clone(),
+            names: self.names.clone(),
+        }
+    }
+}
+
+impl<WorldId> EntityNames<WorldId> {
+    pub fn new() -> EntityNames<WorldId> {
+        EntityNames {
+            phantom: PhantomData,
+            entities: HashMap::new(),
+            names: VecMap::new(),
+        }
+    }
+
+    /// Add or replace name of entity
+    /// Names are unique, so the name is taken away from any entity that had it before
+    pub fn set_name(&mut self, entity: &Entity<WorldId>, name: &str) {
+        self.remove_name(entity);
+
+        if let Some(previous) = self.entities.insert(name.to_string(), entity.clone()) {
+            self.names.remove(&previous.index());
+        }
+
+        self.names.insert(entity.index(), name.to_string());
+    }
+
+    pub fn remove_name(&mut self, entity: &Entity<WorldId>) -> Option<String> {
+        if self.get_name(entity).is_none() {
+            return None;
+        }
+
+        let name = self.names.remove(&entity.index()).unwrap();
+        self.entities.remove(&name);
+        Some(name)
+    }
+
+    pub fn get_name(&self, entity: &Entity<WorldId>) -> Option<&str> {
+        match self.names.get(&entity.index()) {
+            // stale entities with a recycled index don't get the name of the new entity
+            Some(name) if self.entities.get(name) == Some(entity) => Some(&name[..]),
+            _ => None,
+        }
+    }
+
+    pub fn get_entity(&self, name: &str) -> Option<Entity<WorldId>> {
+        self.entities.get(name).map(|entity| entity.clone())
+    }
+
+    pub fn iter(&self) -> Iter<String, Entity<WorldId>> {
+        self.entities.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn entity_destroyed(&mut self, entity: &Entity<WorldId>) {
+        self.remove_name(entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ EntityNames };
+    use entity::{ EntityManager };
+
+    #[test]
+    fn lookup_both_directions() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut names: EntityNames<WorldId1> = EntityNames::new();
+
+        let entity = entity_manager.create_entity();
+        names.set_name(&entity, "player");
+
+        assert_eq!(names.get_name(&entity), Some("player"));
+        assert_eq!(names.get_entity("player"), Some(entity));
+    }
+
+    #[test]
+    fn name_moves_to_new_entity() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut names: EntityNames<WorldId1> = EntityNames::new();
+
+        let entity1 = entity_manager.create_entity();
+        let entity2 = entity_manager.create_entity();
+        names.set_name(&entity1, "player");
+        names.set_name(&entity2, "player");
+
+        assert_eq!(names.get_name(&entity1), None);
+        assert_eq!(names.get_entity("player"), Some(entity2));
+        assert_eq!(names.len(), 1);
+    }
+
+    #[test]
+    fn destroyed_entity_loses_name() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut names: EntityNames<WorldId1> = EntityNames::new();
+
+        let entity = entity_manager.create_entity();
+        names.set_name(&entity, "player");
+        names.entity_destroyed(&entity);
+
+        assert_eq!(names.get_name(&entity), None);
+        assert_eq!(names.get_entity("player"), None);
+    }
+}
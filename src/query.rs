@@ -0,0 +1,382 @@
+use std::marker::PhantomData;
+use std::collections::{ BitVec };
+
+use entity::{ EntityManager, Entity, EntityIterator };
+use component::{ ComponentManager };
+
+/// Condition on an entity component mask, built from the component indices of a Filter
+#[derive(Clone, PartialEq, Debug)]
+pub enum FilterExpr {
+    With(usize),
+    Without(usize),
+    And(Vec<FilterExpr>),
+    Or(Vec<FilterExpr>),
+}
+
+impl FilterExpr {
+    pub fn matches(&self, entity_component_mask: &BitVec) -> bool {
+        match *self {
+            FilterExpr::With(index) => entity_component_mask.get(index).unwrap_or(false),
+            FilterExpr::Without(index) => !entity_component_mask.get(index).unwrap_or(false),
+            FilterExpr::And(ref exprs) => exprs.iter().all(|expr| expr.matches(entity_component_mask)),
+            FilterExpr::Or(ref exprs) => exprs.iter().any(|expr| expr.matches(entity_component_mask)),
+        }
+    }
+}
+
+/// Type level filter, e.g. (With<Position>, Or<(With<Player>, With<Enemy>)>)
+/// Tuples of filters match if all of them match, () matches every entity
+pub trait Filter {
+    fn filter_expr<WorldId>(component_manager: &ComponentManager<WorldId>) -> FilterExpr;
+}
+
+/// Tuples of filters, for Or
+pub trait FilterList {
+    fn filter_exprs<WorldId>(component_manager: &ComponentManager<WorldId>) -> Vec<FilterExpr>;
+}
+
+pub struct With<C>(PhantomData<C>);
+
+pub struct Without<C>(PhantomData<C>);
+
+/// Matches if any of the filters of the tuple match
+pub struct Or<T>(PhantomData<T>);
+
+impl<C: 'static> Filter for With<C> {
+    fn filter_expr<WorldId>(component_manager: &ComponentManager<WorldId>) -> FilterExpr {
+        FilterExpr::With(component_manager.get_component_id::<C>().0)
+    }
+}
+
+impl<C: 'static> Filter for Without<C> {
+    fn filter_expr<WorldId>(component_manager: &ComponentManager<WorldId>) -> FilterExpr {
+        FilterExpr::Without(component_manager.get_component_id::<C>().0)
+    }
+}
+
+impl<T: FilterList> Filter for Or<T> {
+    fn filter_expr<WorldId>(component_manager: &ComponentManager<WorldId>) -> FilterExpr {
+        FilterExpr::Or(T::filter_exprs(component_manager))
+    }
+}
+
+impl Filter for () {
+    fn filter_expr<WorldId>(_: &ComponentManager<WorldId>) -> FilterExpr {
+        FilterExpr::And(Vec::new())
+    }
+}
+
+/// What a query yields for every matching entity
+/// &C skips entities without the component, Option<&C> doesn't
+pub trait Fetch<'a, WorldId: 'a> {
+    type Item;
+
+    fn fetch(component_manager: &'a ComponentManager<WorldId>, entity_manager: &'a EntityManager<WorldId>, entity: &Entity<WorldId>) -> Option<Self::Item>;
+}
+
+impl<'a, WorldId: 'a, C: 'static> Fetch<'a, WorldId> for &'a C {
+    type Item = &'a C;
+
+    fn fetch(component_manager: &'a ComponentManager<WorldId>, _: &'a EntityManager<WorldId>, entity: &Entity<WorldId>) -> Option<&'a C> {
+        component_manager.get_component::<C>(entity)
+    }
+}
+
+impl<'a, WorldId: 'a, C: 'static> Fetch<'a, WorldId> for Option<&'a C> {
+    type Item = Option<&'a C>;
+
+    fn fetch(component_manager: &'a ComponentManager<WorldId>, _: &'a EntityManager<WorldId>, entity: &Entity<WorldId>) -> Option<Option<&'a C>> {
+        Some(component_manager.get_component::<C>(entity))
+    }
+}
+
+/// Component referring to another entity of the same World, e.g. Owner(Entity<WorldId>)
+pub trait Relation<WorldId> {
+    fn target(&self) -> &Entity<WorldId>;
+}
+
+/// Fetches the relation R and what Q fetches from the entity it refers to
+/// Entities whose target was destroyed or doesn't match Q are skipped
+pub struct Join<R, Q>(PhantomData<(R, Q)>);
+
+impl<'a, WorldId: 'a, R: Relation<WorldId> + 'static, Q: Fetch<'a, WorldId>> Fetch<'a, WorldId> for Join<R, Q> {
+    type Item = (&'a R, Q::Item);
+
+    fn fetch(component_manager: &'a ComponentManager<WorldId>, entity_manager: &'a EntityManager<WorldId>, entity: &Entity<WorldId>) -> Option<(&'a R, Q::Item)> {
+        let relation = match component_manager.get_component::<R>(entity) {
+            Some(relation) => relation,
+            None => return None,
+        };
+
+        if !entity_manager.is_valid(relation.target()) {
+            return None;
+        }
+
+        Q::fetch(component_manager, entity_manager, relation.target()).map(|item| (relation, item))
+    }
+}
+
+/// What par_for_each fetches from every entity, &C and Option<&C> like Fetch and &mut C too
+/// A component fetched mutably can't be fetched by another member of the tuple
+pub trait ParFetch<'a, WorldId: 'a> {
+    type Item;
+    // components of one entity, resolved on the calling thread and handed to a single thread
+    type Ptr: Send;
+
+    /// Component indices fetched, with whether they're fetched mutably
+    fn accesses(component_manager: &ComponentManager<WorldId>, accesses: &mut Vec<(usize, bool)>);
+
+    /// Pointers to the components of entity, marking the components fetched mutably as changed
+    fn resolve(component_manager: &mut ComponentManager<WorldId>, entity: &Entity<WorldId>) -> Option<Self::Ptr>;
+
+    /// Unsafe since the components must still be where resolve found them, and no other thread may use ptr
+    unsafe fn from_ptr(ptr: Self::Ptr) -> Self::Item;
+}
+
+/// Component fetched by par_for_each
+pub struct ComponentPtr<C>(*const C);
+
+unsafe impl<C: Sync> Send for ComponentPtr<C> {}
+
+/// Component fetched mutably by par_for_each
+pub struct ComponentMutPtr<C>(*mut C);
+
+// entities of a query are distinct, so every pointer is used by one thread
+unsafe impl<C: Send> Send for ComponentMutPtr<C> {}
+
+impl<'a, WorldId: 'a, C: Sync + 'static> ParFetch<'a, WorldId> for &'a C {
+    type Item = &'a C;
+    type Ptr = ComponentPtr<C>;
+
+    fn accesses(component_manager: &ComponentManager<WorldId>, accesses: &mut Vec<(usize, bool)>) {
+        accesses.push((component_manager.get_component_id::<C>().0, false));
+    }
+
+    fn resolve(component_manager: &mut ComponentManager<WorldId>, entity: &Entity<WorldId>) -> Option<ComponentPtr<C>> {
+        component_manager.get_component::<C>(entity).map(|component| ComponentPtr(component as *const C))
+    }
+
+    unsafe fn from_ptr(ptr: ComponentPtr<C>) -> &'a C {
+        &*ptr.0
+    }
+}
+
+impl<'a, WorldId: 'a, C: Sync + 'static> ParFetch<'a, WorldId> for Option<&'a C> {
+    type Item = Option<&'a C>;
+    type Ptr = Option<ComponentPtr<C>>;
+
+    fn accesses(component_manager: &ComponentManager<WorldId>, accesses: &mut Vec<(usize, bool)>) {
+        accesses.push((component_manager.get_component_id::<C>().0, false));
+    }
+
+    fn resolve(component_manager: &mut ComponentManager<WorldId>, entity: &Entity<WorldId>) -> Option<Option<ComponentPtr<C>>> {
+        Some(<&'a C as ParFetch<'a, WorldId>>::resolve(component_manager, entity))
+    }
+
+    unsafe fn from_ptr(ptr: Option<ComponentPtr<C>>) -> Option<&'a C> {
+        ptr.map(|ptr| &*ptr.0)
+    }
+}
+
+impl<'a, WorldId: 'a, C: Send + 'static> ParFetch<'a, WorldId> for &'a mut C {
+    type Item = &'a mut C;
+    type Ptr = ComponentMutPtr<C>;
+
+    fn accesses(component_manager: &ComponentManager<WorldId>, accesses: &mut Vec<(usize, bool)>) {
+        accesses.push((component_manager.get_component_id::<C>().0, true));
+    }
+
+    fn resolve(component_manager: &mut ComponentManager<WorldId>, entity: &Entity<WorldId>) -> Option<ComponentMutPtr<C>> {
+        component_manager.get_component_mut::<C>(entity).map(|component| ComponentMutPtr(component as *mut C))
+    }
+
+    unsafe fn from_ptr(ptr: ComponentMutPtr<C>) -> &'a mut C {
+        &mut *ptr.0
+    }
+}
+
+macro_rules! impl_query_tuple {
+    ($(($T:ident $t:ident)),+) => (
+        impl<$($T: Filter),+> FilterList for ($($T,)+) {
+            fn filter_exprs<WorldId>(component_manager: &ComponentManager<WorldId>) -> Vec<FilterExpr> {
+                vec![$($T::filter_expr(component_manager)),+]
+            }
+        }
+
+        impl<$($T: Filter),+> Filter for ($($T,)+) {
+            fn filter_expr<WorldId>(component_manager: &ComponentManager<WorldId>) -> FilterExpr {
+                FilterExpr::And(<($($T,)+) as FilterList>::filter_exprs(component_manager))
+            }
+        }
+
+        impl<'a, WorldId: 'a, $($T: Fetch<'a, WorldId>),+> Fetch<'a, WorldId> for ($($T,)+) {
+            type Item = ($($T::Item,)+);
+
+            fn fetch(component_manager: &'a ComponentManager<WorldId>, entity_manager: &'a EntityManager<WorldId>, entity: &Entity<WorldId>) -> Option<($($T::Item,)+)> {
+                $(let $t = match $T::fetch(component_manager, entity_manager, entity) {
+                    Some(item) => item,
+                    None => return None,
+                };)+
+                Some(($($t,)+))
+            }
+        }
+
+        impl<'a, WorldId: 'a, $($T: ParFetch<'a, WorldId>),+> ParFetch<'a, WorldId> for ($($T,)+) {
+            type Item = ($($T::Item,)+);
+            type Ptr = ($($T::Ptr,)+);
+
+            fn accesses(component_manager: &ComponentManager<WorldId>, accesses: &mut Vec<(usize, bool)>) {
+                $($T::accesses(component_manager, accesses);)+
+            }
+
+            fn resolve(component_manager: &mut ComponentManager<WorldId>, entity: &Entity<WorldId>) -> Option<($($T::Ptr,)+)> {
+                $(let $t = match $T::resolve(component_manager, entity) {
+                    Some(ptr) => ptr,
+                    None => return None,
+                };)+
+                Some(($($t,)+))
+            }
+
+            unsafe fn from_ptr(ptr: ($($T::Ptr,)+)) -> ($($T::Item,)+) {
+                let ($($t,)+) = ptr;
+                ($($T::from_ptr($t),)+)
+            }
+        }
+    )
+}
+
+for_each_tuple!(impl_query_tuple, [], (A a), (B b), (C c), (D d), (E e), (F f), (G g), (H h), (I i), (J j), (K k), (L l), (M m), (N n), (O o), (P p));
+
+/// Entities matching the filter, with what Q fetches from them
+pub struct FilterIterator<'a, WorldId: 'a, Q> {
+    phantom: PhantomData<Q>,
+    component_manager: &'a ComponentManager<WorldId>,
+    entity_manager: &'a EntityManager<WorldId>,
+    entities: EntityIterator<'a, WorldId>,
+    filter_expr: FilterExpr,
+}
+
+impl<'a, WorldId: 'a, Q> FilterIterator<'a, WorldId, Q> {
+    pub fn new(component_manager: &'a ComponentManager<WorldId>, entity_manager: &'a EntityManager<WorldId>, filter_expr: FilterExpr) -> FilterIterator<'a, WorldId, Q> {
+        FilterIterator {
+            phantom: PhantomData,
+            component_manager: component_manager,
+            entity_manager: entity_manager,
+            entities: entity_manager.entities(),
+            filter_expr: filter_expr,
+        }
+    }
+}
+
+impl<'a, WorldId: 'a, Q: Fetch<'a, WorldId>> Iterator for FilterIterator<'a, WorldId, Q> {
+    type Item = (Entity<WorldId>, Q::Item);
+
+    fn next(&mut self) -> Option<(Entity<WorldId>, Q::Item)> {
+        for entity in self.entities.by_ref() {
+            if !self.filter_expr.matches(self.component_manager.get_entity_component_mask(&entity)) {
+                continue;
+            }
+
+            if let Some(item) = Q::fetch(self.component_manager, self.entity_manager, &entity) {
+                return Some((entity, item));
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{ VecMap };
+
+    use super::{ With, Without, Or, Relation, Join };
+    use entity::{ EntityManager, Entity };
+    use component::{ ComponentManager };
+
+    #[test]
+    fn optional_fetch_and_or_filter() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+
+        #[derive(PartialEq, Debug)]
+        struct Position(isize);
+        #[derive(PartialEq, Debug)]
+        struct Velocity(isize);
+        struct Player;
+        struct Enemy;
+        struct Dead;
+
+        component_manager.register_component::<Position>(Box::new(VecMap::new()));
+        component_manager.register_component::<Velocity>(Box::new(VecMap::new()));
+        component_manager.register_component::<Player>(Box::new(VecMap::new()));
+        component_manager.register_component::<Enemy>(Box::new(VecMap::new()));
+        component_manager.register_component::<Dead>(Box::new(VecMap::new()));
+
+        let player = entity_manager.create_entity();
+        component_manager.entity_created(&player);
+        component_manager.assign_component(&player, Position(1));
+        component_manager.assign_component(&player, Velocity(2));
+        component_manager.assign_component(&player, Player);
+
+        let enemy = entity_manager.create_entity();
+        component_manager.entity_created(&enemy);
+        component_manager.assign_component(&enemy, Position(3));
+        component_manager.assign_component(&enemy, Enemy);
+
+        let dead_enemy = entity_manager.create_entity();
+        component_manager.entity_created(&dead_enemy);
+        component_manager.assign_component(&dead_enemy, Position(4));
+        component_manager.assign_component(&dead_enemy, Enemy);
+        component_manager.assign_component(&dead_enemy, Dead);
+
+        let tree = entity_manager.create_entity();
+        component_manager.entity_created(&tree);
+        component_manager.assign_component(&tree, Position(5));
+
+        let found: Vec<_> = component_manager.filter::<(&Position, Option<&Velocity>), (Or<(With<Player>, With<Enemy>)>, Without<Dead>)>(&entity_manager)
+            .map(|(entity, (position, velocity))| (entity.index(), position.0, velocity.map(|velocity| velocity.0)))
+            .collect();
+        assert_eq!(found, vec![(player.index(), 1, Some(2)), (enemy.index(), 3, None)]);
+
+        assert_eq!(component_manager.filter::<&Position, ()>(&entity_manager).count(), 4);
+        assert_eq!(component_manager.filter::<&Velocity, ()>(&entity_manager).count(), 1);
+    }
+
+    #[test]
+    fn join_relation() {
+        struct WorldId1;
+        let mut entity_manager: EntityManager<WorldId1> = EntityManager::new(256);
+        let mut component_manager: ComponentManager<WorldId1> = ComponentManager::new(256);
+
+        struct Owner(Entity<WorldId1>);
+        struct Team(usize);
+
+        impl Relation<WorldId1> for Owner {
+            fn target(&self) -> &Entity<WorldId1> {
+                &self.0
+            }
+        }
+
+        component_manager.register_component::<Owner>(Box::new(VecMap::new()));
+        component_manager.register_component::<Team>(Box::new(VecMap::new()));
+
+        let player = entity_manager.create_entity();
+        component_manager.entity_created(&player);
+        component_manager.assign_component(&player, Team(2));
+
+        let sword = entity_manager.create_entity();
+        component_manager.entity_created(&sword);
+        component_manager.assign_component(&sword, Owner(player.clone()));
+
+        let teams: Vec<usize> = component_manager.filter::<Join<Owner, &Team>, ()>(&entity_manager)
+            .map(|(_, (_, team))| team.0)
+            .collect();
+        assert_eq!(teams, vec![2]);
+
+        component_manager.entity_destroyed(&player);
+        entity_manager.destroy_entity(player);
+        assert_eq!(component_manager.filter::<Join<Owner, &Team>, ()>(&entity_manager).count(), 0);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,472 @@
+use std::marker::PhantomData;
+use std::collections::{ VecMap, HashMap };
+
+use entity::{ Entity };
+use component::{ ComponentId };
+use world::{ World };
+use bytes::{ write_u32, write_u64, write_string, read_u32, read_u64, read_bytes, read_string };
+
+// Packet layout, all integers little endian
+// u64 sequence
+// u32 component count, per component: string register_serialize name
+// u32 destroyed entity count, per entity: u32 index, u32 version
+// u32 entity count, per entity:
+//     u32 index, u32 version
+//     u32 assigned component count, per component: u32 position in the component names, u32 length, bytes
+//     u32 removed component count, per component: u32 position in the component names
+// strings are a u32 length followed by UTF-8
+// Components are identified by their register_serialize name, so registration order doesn't matter
+// Only components registered with register_serialize are replicated
+
+// Replicated components of every entity, by entity index: entity version and component ids
+type ReplicatedState = VecMap<(usize, Vec<ComponentId>)>;
+
+/// Server side of the replication of a World to a single client
+/// Every packet contains all changes since the last packet acknowledged by the client,
+/// so lost packets don't need to be resent
+pub struct ReplicationServer<WorldId> {
+    phantom: PhantomData<WorldId>,
+    next_sequence: u64,
+    // state of the client as of the last acknowledged packet
+    acked_state: ReplicatedState,
+    // components changed after this tick might not be known to the client
+    acked_tick: usize,
+    // sequence, tick and state of every unacknowledged packet
+    pending: Vec<(u64, usize, ReplicatedState)>,
+}
+
+impl<WorldId> ReplicationServer<WorldId> {
+    pub fn new() -> ReplicationServer<WorldId> {
+        ReplicationServer {
+            phantom: PhantomData,
+            next_sequence: 0,
+            acked_state: VecMap::new(),
+            acked_tick: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Packet with all changes to world since the last acknowledged packet
+    /// Increments the tick of world, so later changes are never mistaken for sent ones
+    pub fn write_delta(&mut self, world: &mut World<WorldId>) -> Vec<u8> {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+
+        let mut state = VecMap::new();
+        for entity in world.entities() {
+            let component_ids: Vec<ComponentId> = world.get_entity_components(&entity).into_iter()
+                .filter(|component_id| world.get_component_info(*component_id).is_serializable())
+                .collect();
+            state.insert(entity.index(), (entity.version(), component_ids));
+        }
+
+        let destroyed: Vec<(usize, usize)> = self.acked_state.iter()
+            .filter(|&(index, &(version, _))| match state.get(&index) {
+                Some(&(current_version, _)) => current_version != version,
+                None => true,
+            })
+            .map(|(index, &(version, _))| (index, version))
+            .collect();
+
+        let mut entities = Vec::new();
+        for (index, &(version, ref component_ids)) in state.iter() {
+            let entity = Entity::new(index, version);
+
+            let acked_component_ids = match self.acked_state.get(&index) {
+                Some(&(acked_version, ref acked_component_ids)) if acked_version == version => Some(acked_component_ids),
+                _ => None,
+            };
+
+            let assigned: Vec<ComponentId> = component_ids.iter()
+                .cloned()
+                .filter(|component_id| match acked_component_ids {
+                    Some(acked_component_ids) => !acked_component_ids.contains(component_id)
+                        || world.is_changed_since_id(&entity, *component_id, self.acked_tick),
+                    None => true,
+                })
+                .collect();
+
+            let removed: Vec<ComponentId> = match acked_component_ids {
+                Some(acked_component_ids) => acked_component_ids.iter()
+                    .cloned()
+                    .filter(|component_id| !component_ids.contains(component_id))
+                    .collect(),
+                None => Vec::new(),
+            };
+
+            // new entities are always sent, even without components
+            if acked_component_ids.is_none() || !assigned.is_empty() || !removed.is_empty() {
+                entities.push((entity, assigned, removed));
+            }
+        }
+
+        // names of the components in the packet, by component index
+        let mut positions = VecMap::new();
+        let mut buffer = Vec::new();
+        write_u64(&mut buffer, sequence);
+
+        let mut component_ids: Vec<ComponentId> = entities.iter()
+            .flat_map(|&(_, ref assigned, ref removed)| assigned.iter().chain(removed.iter()).cloned())
+            .collect();
+        component_ids.sort_by(|a, b| a.0.cmp(&b.0));
+        component_ids.dedup();
+
+        write_u32(&mut buffer, component_ids.len() as u32);
+        for (position, component_id) in component_ids.iter().enumerate() {
+            positions.insert(component_id.0, position as u32);
+            write_string(&mut buffer, world.get_component_info(*component_id).serialize_name.as_ref().unwrap());
+        }
+
+        write_u32(&mut buffer, destroyed.len() as u32);
+        for &(index, version) in destroyed.iter() {
+            write_u32(&mut buffer, index as u32);
+            write_u32(&mut buffer, version as u32);
+        }
+
+        write_u32(&mut buffer, entities.len() as u32);
+        for &(ref entity, ref assigned, ref removed) in entities.iter() {
+            write_u32(&mut buffer, entity.index() as u32);
+            write_u32(&mut buffer, entity.version() as u32);
+
+            write_u32(&mut buffer, assigned.len() as u32);
+            for component_id in assigned.iter() {
+                let bytes = world.encode_component(entity, *component_id).unwrap();
+                write_u32(&mut buffer, positions[component_id.0]);
+                write_u32(&mut buffer, bytes.len() as u32);
+                buffer.push_all(&bytes[..]);
+            }
+
+            write_u32(&mut buffer, removed.len() as u32);
+            for component_id in removed.iter() {
+                write_u32(&mut buffer, positions[component_id.0]);
+            }
+        }
+
+        self.pending.push((sequence, world.tick(), state));
+        world.increment_tick();
+
+        buffer
+    }
+
+    /// Called with the sequence returned by ReplicationClient::read_delta
+    pub fn acknowledge(&mut self, sequence: u64) {
+        let position = self.pending.iter().position(|&(pending_sequence, _, _)| pending_sequence == sequence);
+
+        if let Some(position) = position {
+            let (_, tick, state) = self.pending.remove(position);
+            self.acked_state = state;
+            self.acked_tick = tick;
+
+            // older packets are superseded
+            self.pending.retain(|&(pending_sequence, _, _)| pending_sequence > sequence);
+        }
+    }
+}
+
+/// Client side of replication, applies packets of a ReplicationServer to a World
+pub struct ReplicationClient<WorldId> {
+    // by server entity index: server entity version and client entity
+    // not a VecMap, the indices come from the network
+    entities: HashMap<usize, (usize, Entity<WorldId>)>,
+    last_sequence: Option<u64>,
+}
+
+// Packet read and checked against the World, so applying it can't fail halfway
+struct Delta<'a> {
+    sequence: u64,
+    destroyed: Vec<(usize, usize)>,
+    entities: Vec<EntityDelta<'a>>,
+}
+
+struct EntityDelta<'a> {
+    index: usize,
+    version: usize,
+    assigned: Vec<(ComponentId, &'a [u8])>,
+    removed: Vec<ComponentId>,
+}
+
+fn read_packet<'a, WorldId>(world: &World<WorldId>, packet: &'a [u8]) -> Option<Delta<'a>> {
+    let mut packet = packet;
+
+    let sequence = try_read!(read_u64(&mut packet));
+
+    // rejects components the client doesn't know under the same name
+    let mut component_ids = Vec::new();
+    let component_count = try_read!(read_u32(&mut packet));
+    for _ in 0..component_count {
+        let name = try_read!(read_string(&mut packet));
+        component_ids.push(try_read!(world.get_component_id_by_serialize_name(&name)));
+    }
+
+    let mut destroyed = Vec::new();
+    let destroyed_count = try_read!(read_u32(&mut packet));
+    for _ in 0..destroyed_count {
+        let index = try_read!(read_u32(&mut packet)) as usize;
+        let version = try_read!(read_u32(&mut packet)) as usize;
+        destroyed.push((index, version));
+    }
+
+    let mut entities = Vec::new();
+    let entity_count = try_read!(read_u32(&mut packet));
+    for _ in 0..entity_count {
+        let index = try_read!(read_u32(&mut packet)) as usize;
+        let version = try_read!(read_u32(&mut packet)) as usize;
+
+        let mut assigned = Vec::new();
+        let assigned_count = try_read!(read_u32(&mut packet));
+        for _ in 0..assigned_count {
+            let component_id = *try_read!(component_ids.get(try_read!(read_u32(&mut packet)) as usize));
+            let length = try_read!(read_u32(&mut packet)) as usize;
+            let bytes = try_read!(read_bytes(&mut packet, length));
+
+            if !world.can_decode_component(component_id, bytes) {
+                return None;
+            }
+
+            assigned.push((component_id, bytes));
+        }
+
+        let mut removed = Vec::new();
+        let removed_count = try_read!(read_u32(&mut packet));
+        for _ in 0..removed_count {
+            let component_id = *try_read!(component_ids.get(try_read!(read_u32(&mut packet)) as usize));
+            removed.push(component_id);
+        }
+
+        entities.push(EntityDelta {
+            index: index,
+            version: version,
+            assigned: assigned,
+            removed: removed,
+        });
+    }
+
+    Some(Delta {
+        sequence: sequence,
+        destroyed: destroyed,
+        entities: entities,
+    })
+}
+
+impl<WorldId> ReplicationClient<WorldId> {
+    pub fn new() -> ReplicationClient<WorldId> {
+        ReplicationClient {
+            entities: HashMap::new(),
+            last_sequence: None,
+        }
+    }
+
+    /// Client entity of a server entity
+    pub fn get_entity<ServerId>(&self, server_entity: &Entity<ServerId>) -> Option<Entity<WorldId>> {
+        match self.entities.get(&server_entity.index()) {
+            Some(&(version, ref entity)) if version == server_entity.version() => Some(entity.clone()),
+            _ => None,
+        }
+    }
+
+    /// Apply packet to world, returning the sequence to acknowledge to the server
+    /// None if the packet is malformed or older than an already applied packet, world is unchanged then
+    pub fn read_delta(&mut self, world: &mut World<WorldId>, packet: &[u8]) -> Option<u64> {
+        let delta = try_read!(read_packet(world, packet));
+
+        if self.last_sequence.map_or(false, |last_sequence| delta.sequence <= last_sequence) {
+            return None;
+        }
+
+        for &(index, version) in delta.destroyed.iter() {
+            self.destroy_entity(world, index, version);
+        }
+
+        for entity_delta in delta.entities.iter() {
+            let index = entity_delta.index;
+
+            let entity = match self.entities.get(&index) {
+                Some(&(known_version, ref entity)) if known_version == entity_delta.version => Some(entity.clone()),
+                _ => None,
+            };
+
+            let entity = match entity {
+                Some(entity) => entity,
+                None => {
+                    // the server reused the index, so the previous entity is gone
+                    if let Some(known_version) = self.entities.get(&index).map(|&(known_version, _)| known_version) {
+                        self.destroy_entity(world, index, known_version);
+                    }
+
+                    let entity = world.create_entity();
+                    self.entities.insert(index, (entity_delta.version, entity.clone()));
+                    entity
+                },
+            };
+
+            for &(component_id, bytes) in entity_delta.assigned.iter() {
+                world.decode_component(&entity, component_id, bytes);
+            }
+
+            // all at once, the server removes components along with the ones requiring them
+            world.remove_component_ids(&entity, &entity_delta.removed[..]);
+        }
+
+        self.last_sequence = Some(delta.sequence);
+        Some(delta.sequence)
+    }
+
+    fn destroy_entity(&mut self, world: &mut World<WorldId>, index: usize, version: usize) {
+        let entity = match self.entities.get(&index) {
+            Some(&(known_version, ref entity)) if known_version == version => entity.clone(),
+            _ => return,
+        };
+
+        self.entities.remove(&index);
+        world.destroy_entity(entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{ VecMap };
+
+    use super::{ ReplicationServer, ReplicationClient };
+    use world::{ World };
+
+    struct ServerId;
+    struct ClientId;
+
+    #[derive(RustcEncodable, RustcDecodable, PartialEq, Debug)]
+    struct Position(isize, isize);
+
+    #[derive(RustcEncodable, RustcDecodable, PartialEq, Debug)]
+    struct Health(isize);
+
+    fn worlds() -> (World<ServerId>, World<ClientId>) {
+        let mut server_world: World<ServerId> = World::new();
+        server_world.register_component::<Position>(Box::new(VecMap::new()));
+        server_world.register_serialize::<Position>("Position");
+        server_world.register_component::<Health>(Box::new(VecMap::new()));
+        server_world.register_serialize::<Health>("Health");
+
+        let mut client_world: World<ClientId> = World::new();
+        client_world.register_component::<Position>(Box::new(VecMap::new()));
+        client_world.register_serialize::<Position>("Position");
+        client_world.register_component::<Health>(Box::new(VecMap::new()));
+        client_world.register_serialize::<Health>("Health");
+
+        (server_world, client_world)
+    }
+
+    #[test]
+    fn replicate_creation_and_destruction() {
+        let (mut server_world, mut client_world) = worlds();
+        let mut server = ReplicationServer::new();
+        let mut client = ReplicationClient::new();
+
+        let entity = server_world.create_entity();
+        server_world.assign_component(&entity, Position(1, 2));
+
+        let packet = server.write_delta(&mut server_world);
+        let sequence = client.read_delta(&mut client_world, &packet[..]).unwrap();
+        server.acknowledge(sequence);
+
+        let client_entity = client.get_entity(&entity).unwrap();
+        assert_eq!(client_world.get_component::<Position>(&client_entity), Some(&Position(1, 2)));
+
+        server_world.destroy_entity(entity.clone());
+
+        let packet = server.write_delta(&mut server_world);
+        client.read_delta(&mut client_world, &packet[..]).unwrap();
+
+        assert!(!client_world.is_valid(&client_entity));
+        assert_eq!(client.get_entity(&entity), None);
+    }
+
+    #[test]
+    fn replicate_only_changes_since_acknowledged() {
+        let (mut server_world, mut client_world) = worlds();
+        let mut server = ReplicationServer::new();
+        let mut client = ReplicationClient::new();
+
+        let entity = server_world.create_entity();
+        server_world.assign_component(&entity, Position(1, 2));
+        server_world.assign_component(&entity, Health(10));
+
+        let packet = server.write_delta(&mut server_world);
+        let sequence = client.read_delta(&mut client_world, &packet[..]).unwrap();
+        server.acknowledge(sequence);
+
+        let unchanged_packet = server.write_delta(&mut server_world);
+
+        server_world.get_component_mut::<Health>(&entity).unwrap().0 = 5;
+        server_world.remove_component::<Position>(&entity);
+
+        // lost
+        server.write_delta(&mut server_world);
+
+        let packet = server.write_delta(&mut server_world);
+        assert!(packet.len() > unchanged_packet.len());
+        let sequence = client.read_delta(&mut client_world, &packet[..]).unwrap();
+        server.acknowledge(sequence);
+
+        let client_entity = client.get_entity(&entity).unwrap();
+        assert_eq!(client_world.get_component::<Health>(&client_entity), Some(&Health(5)));
+        assert_eq!(client_world.get_component::<Position>(&client_entity), None);
+
+        // stale packets are ignored
+        assert_eq!(client.read_delta(&mut client_world, &unchanged_packet[..]), None);
+    }
+
+    #[test]
+    fn malformed_packet() {
+        let (_, mut client_world) = worlds();
+        let mut client: ReplicationClient<ClientId> = ReplicationClient::new();
+
+        assert_eq!(client.read_delta(&mut client_world, &[0, 1, 2][..]), None);
+    }
+
+    #[test]
+    fn unknown_component_changes_nothing() {
+        let (mut server_world, mut client_world) = worlds();
+        let mut server = ReplicationServer::new();
+        let mut client = ReplicationClient::new();
+
+        #[derive(RustcEncodable, RustcDecodable)]
+        struct Name(String);
+
+        // only the server knows it
+        server_world.register_component::<Name>(Box::new(VecMap::new()));
+        server_world.register_serialize::<Name>("Name");
+
+        let entity = server_world.create_entity();
+        server_world.assign_component(&entity, Position(1, 2));
+        server_world.assign_component(&entity, Name("Player".to_string()));
+
+        let packet = server.write_delta(&mut server_world);
+        assert_eq!(client.read_delta(&mut client_world, &packet[..]), None);
+
+        assert_eq!(client_world.entities().count(), 0);
+        assert_eq!(client.get_entity(&entity), None);
+    }
+
+    #[test]
+    fn different_registration_order() {
+        let (mut server_world, _) = worlds();
+        let mut server = ReplicationServer::new();
+        let mut client = ReplicationClient::new();
+
+        let mut client_world: World<ClientId> = World::new();
+        client_world.register_component::<Health>(Box::new(VecMap::new()));
+        client_world.register_serialize::<Health>("Health");
+        client_world.register_component::<Position>(Box::new(VecMap::new()));
+        client_world.register_serialize::<Position>("Position");
+
+        let entity = server_world.create_entity();
+        server_world.assign_component(&entity, Position(1, 2));
+        server_world.assign_component(&entity, Health(10));
+
+        let packet = server.write_delta(&mut server_world);
+        client.read_delta(&mut client_world, &packet[..]).unwrap();
+
+        let client_entity = client.get_entity(&entity).unwrap();
+        assert_eq!(client_world.get_component::<Position>(&client_entity), Some(&Position(1, 2)));
+        assert_eq!(client_world.get_component::<Health>(&client_entity), Some(&Health(10)));
+    }
+}
@@ -0,0 +1,161 @@
+use std::sync::atomic::{ AtomicUsize, ATOMIC_USIZE_INIT, Ordering };
+
+use entity::{ Entity };
+use component::{ ComponentList };
+use system::{ System };
+use world::{ World };
+
+static NEXT_WORLD_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// WorldId of all RuntimeWorlds, which tell their entities apart at runtime instead
+pub struct Runtime;
+
+/// Entity of any RuntimeWorld, so entities of different worlds can be stored together
+#[derive(Clone, PartialEq, Debug)]
+pub struct RuntimeEntity {
+    world_id: u32,
+    entity: Entity<Runtime>,
+}
+
+impl RuntimeEntity {
+    pub fn world_id(&self) -> u32 {
+        self.world_id
+    }
+
+    pub fn entity(&self) -> &Entity<Runtime> {
+        &self.entity
+    }
+}
+
+/// World for when the number of worlds is only known at runtime, e.g. one per server match
+/// Using an entity with the wrong RuntimeWorld is caught at runtime instead of at compile time
+pub struct RuntimeWorld {
+    id: u32,
+    world: World<Runtime>,
+}
+
+impl RuntimeWorld {
+    pub fn new() -> RuntimeWorld {
+        RuntimeWorld {
+            id: NEXT_WORLD_ID.fetch_add(1, Ordering::Relaxed) as u32,
+            world: World::new(),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Entities and systems of the underlying World aren't checked for their world id
+    pub fn world(&self) -> &World<Runtime> {
+        &self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut World<Runtime> {
+        &mut self.world
+    }
+
+    // *** EntityManager ***
+
+    pub fn create_entity(&mut self) -> RuntimeEntity {
+        RuntimeEntity {
+            world_id: self.id,
+            entity: self.world.create_entity(),
+        }
+    }
+
+    pub fn destroy_entity(&mut self, entity: RuntimeEntity) {
+        assert!(entity.world_id == self.id, "Tried to destroy entity of another world");
+
+        self.world.destroy_entity(entity.entity)
+    }
+
+    /// False for entities of other worlds
+    pub fn is_valid(&self, entity: &RuntimeEntity) -> bool {
+        entity.world_id == self.id && self.world.is_valid(&entity.entity)
+    }
+
+    // *** ComponentManager ***
+
+    pub fn register_component<C: 'static>(&mut self, component_list: Box<ComponentList<C> + 'static>) {
+        self.world.register_component(component_list)
+    }
+
+    pub fn assign_component<C: 'static>(&mut self, entity: &RuntimeEntity, component: C) {
+        assert!(self.is_valid(entity));
+
+        self.world.assign_component(&entity.entity, component)
+    }
+
+    pub fn remove_component<C: 'static>(&mut self, entity: &RuntimeEntity) -> bool {
+        assert!(self.is_valid(entity));
+
+        self.world.remove_component::<C>(&entity.entity)
+    }
+
+    pub fn has_component<C: 'static>(&self, entity: &RuntimeEntity) -> bool {
+        assert!(self.is_valid(entity));
+
+        self.world.has_component::<C>(&entity.entity)
+    }
+
+    pub fn get_component<C: 'static>(&self, entity: &RuntimeEntity) -> Option<&C> {
+        assert!(self.is_valid(entity));
+
+        self.world.get_component::<C>(&entity.entity)
+    }
+
+    pub fn get_component_mut<C: 'static>(&mut self, entity: &RuntimeEntity) -> Option<&mut C> {
+        assert!(self.is_valid(entity));
+
+        self.world.get_component_mut::<C>(&entity.entity)
+    }
+
+    // *** SystemManager ***
+
+    pub fn register_system<S>(&mut self, system: S) where S: System<Runtime, S> + 'static {
+        self.world.register_system(system)
+    }
+
+    pub fn update_system<A, S>(&mut self, args: &A) where S: System<Runtime, S> + 'static {
+        self.world.update_system::<A, S>(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{ VecMap };
+
+    use super::{ RuntimeWorld };
+
+    #[test]
+    fn entities_of_other_worlds_are_invalid() {
+        let mut world1 = RuntimeWorld::new();
+        let mut world2 = RuntimeWorld::new();
+
+        let entities = vec![world1.create_entity(), world2.create_entity()];
+
+        // same index and version, different world
+        assert_eq!(entities[0].entity(), entities[1].entity());
+
+        assert!(world1.is_valid(&entities[0]));
+        assert!(!world1.is_valid(&entities[1]));
+        assert!(!world2.is_valid(&entities[0]));
+        assert!(world2.is_valid(&entities[1]));
+    }
+
+    #[test]
+    #[should_fail]
+    fn assign_component_to_entity_of_other_world() {
+        struct Cmp1;
+
+        let mut world1 = RuntimeWorld::new();
+        let mut world2 = RuntimeWorld::new();
+
+        world1.register_component::<Cmp1>(Box::new(VecMap::new()));
+        world2.register_component::<Cmp1>(Box::new(VecMap::new()));
+
+        let entity = world1.create_entity();
+        world2.assign_component(&entity, Cmp1);
+    }
+}
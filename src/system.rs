@@ -5,34 +5,67 @@ use anymap::AnyMap;
 use entity::{ EntityManager };
 use component::{ ComponentManager };
 use control::{ Control };
+use names::{ EntityNames };
 
 pub trait System<WorldId, S> {
     fn update<A>(&mut self, entity_manager: &EntityManager<WorldId>, component_manager: &ComponentManager<WorldId>, &mut Control<WorldId, S>, args: &A);
 }
 
+struct SystemData<S> {
+    system: S,
+    // index into last_runs
+    index: usize,
+}
+
 pub struct SystemManager<WorldId> {
     phantom: PhantomData<WorldId>,
-    systems: AnyMap
+    systems: AnyMap,
+    // ComponentManager tick of the last update of each system, 0 if never updated
+    last_runs: Vec<usize>,
 }
 
 impl<WorldId> SystemManager<WorldId> {
     pub fn new() -> SystemManager<WorldId> {
         SystemManager {
             phantom: PhantomData,
-            systems: AnyMap::new()
+            systems: AnyMap::new(),
+            last_runs: Vec::new(),
         }
     }
 
     pub fn register<S>(&mut self, system: S) where S: System<WorldId, S> + 'static {
-        self.systems.insert(system);
+        let index = match self.systems.get::<SystemData<S>>() {
+            Some(system_data) => system_data.index,
+            None => {
+                self.last_runs.push(0);
+                self.last_runs.len() - 1
+            }
+        };
+        self.last_runs[index] = 0;
+
+        self.systems.insert(SystemData {
+            system: system,
+            index: index,
+        });
     }
 
-    pub fn update<A, S>(&mut self, entity_manager: &mut EntityManager<WorldId>, component_manager: &mut ComponentManager<WorldId>, args: &A) where S: System<WorldId, S> + 'static {
-        match self.systems.get_mut::<S>() {
-            Some(system) => {
-                let mut control: Control<WorldId, S> = Control::new();
-                system.update(entity_manager, component_manager, &mut control, args);
-                control.apply(entity_manager, system);
+    /// Run system S, then drop removed components every registered system has seen
+    /// Removals are kept while any registered system hasn't run since, including systems
+    /// that never ran, so a system that is registered but not updated makes them pile up
+    pub fn update<A, S>(&mut self, entity_manager: &mut EntityManager<WorldId>, component_manager: &mut ComponentManager<WorldId>, entity_names: &mut EntityNames<WorldId>, args: &A) where S: System<WorldId, S> + 'static {
+        match self.systems.get_mut::<SystemData<S>>() {
+            Some(system_data) => {
+                let this_run = component_manager.tick();
+
+                let mut control: Control<WorldId, S> = Control::new(self.last_runs[system_data.index]);
+                system_data.system.update(entity_manager, component_manager, &mut control, args);
+                control.apply(entity_manager, component_manager, entity_names, &mut system_data.system);
+
+                self.last_runs[system_data.index] = this_run;
+                let seen_by_all = self.last_runs.iter().cloned().min().unwrap_or(0);
+                component_manager.clear_removed_until(seen_by_all);
+                // anything changed after this update is newer than this_run
+                component_manager.increment_tick();
             },
             None => panic!("Tried to update unregistered system")
         }
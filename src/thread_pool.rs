@@ -0,0 +1,181 @@
+use std::mem;
+use std::sync::mpsc::{ channel, Receiver, Sender, SendError };
+use std::thread;
+
+// FnOnce can't be called through a Box yet
+trait Job: Send {
+    fn run(self: Box<Self>);
+}
+
+impl<F: FnOnce() + Send> Job for F {
+    fn run(self: Box<F>) {
+        let job = *self;
+        job()
+    }
+}
+
+/// Worker threads kept alive between calls, so running jobs every frame doesn't pay for starting threads
+/// Workers are started when first needed
+pub struct ThreadPool {
+    workers: Vec<Sender<Box<Job + 'static>>>,
+}
+
+// Reports a job as finished when dropped, with whether it panicked
+// Created when the job starts, so a job dropped without running doesn't report
+struct Report {
+    index: usize,
+    sender: Sender<(usize, bool)>,
+}
+
+impl Drop for Report {
+    fn drop(&mut self) {
+        let _ = self.sender.send((self.index, thread::panicking()));
+    }
+}
+
+// Waits for every job sent so far when dropped, since jobs borrow from the caller of scoped
+// This also covers the caller panicking while sending jobs
+struct Pending {
+    sender: Option<Sender<(usize, bool)>>,
+    receiver: Receiver<(usize, bool)>,
+}
+
+impl Pending {
+    // Reports of all jobs that ran, once every job finished or was dropped
+    fn wait(&mut self) -> Vec<(usize, bool)> {
+        self.sender = None;
+        self.receiver.iter().collect()
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.sender = None;
+        for _ in self.receiver.iter() {}
+    }
+}
+
+fn spawn_worker() -> Sender<Box<Job + 'static>> {
+    let (sender, receiver) = channel::<Box<Job + 'static>>();
+
+    thread::spawn(move || {
+        for job in receiver.iter() {
+            job.run();
+        }
+    });
+
+    sender
+}
+
+impl ThreadPool {
+    pub fn new() -> ThreadPool {
+        ThreadPool {
+            workers: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Run every job on its own worker, returning once all of them finished
+    /// Jobs can borrow from the caller, since they can't outlive this call
+    /// Panics if a job panicked, after waiting for the others
+    pub fn scoped<'a, F>(&mut self, jobs: Vec<F>) where F: FnOnce() + Send + 'a {
+        while self.workers.len() < jobs.len() {
+            self.workers.push(spawn_worker());
+        }
+
+        let job_count = jobs.len();
+        let (sender, receiver) = channel();
+        let mut pending = Pending {
+            sender: Some(sender),
+            receiver: receiver,
+        };
+
+        for (i, job) in jobs.into_iter().enumerate() {
+            let sender = pending.sender.as_ref().unwrap().clone();
+            let job: Box<Job + 'a> = Box::new(move || {
+                let _report = Report { index: i, sender: sender };
+                job();
+            });
+
+            // sound since pending waits for every job sent, even if this panics before the end
+            let job: Box<Job + 'static> = unsafe { mem::transmute(job) };
+
+            // a worker that stopped otherwise is gone, replace it
+            if let Err(SendError(job)) = self.workers[i].send(job) {
+                self.workers[i] = spawn_worker();
+                self.workers[i].send(job).ok().expect("Tried to send job to a new worker");
+            }
+        }
+
+        let reports = pending.wait();
+
+        // a worker whose job panicked may still be unwinding, so it can't take the next job
+        let mut panicked = reports.len() < job_count;
+        for &(i, job_panicked) in reports.iter() {
+            if job_panicked {
+                self.workers[i] = spawn_worker();
+                panicked = true;
+            }
+        }
+
+        if panicked {
+            panic!("Tried to run a job that panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{ Arc, Mutex };
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    use std::thread;
+
+    use super::{ ThreadPool };
+
+    #[test]
+    fn scoped_jobs_borrow() {
+        let mut thread_pool = ThreadPool::new();
+        let sum = AtomicUsize::new(0);
+
+        for _ in 0..3 {
+            let jobs: Vec<_> = (1..5).map(|i| {
+                let sum = &sum;
+                move || { sum.fetch_add(i, Ordering::SeqCst); }
+            }).collect();
+            thread_pool.scoped(jobs);
+        }
+
+        assert_eq!(sum.load(Ordering::SeqCst), 30);
+        assert_eq!(thread_pool.len(), 4);
+    }
+
+    #[test]
+    fn scoped_after_panicked_job() {
+        let thread_pool = Arc::new(Mutex::new(ThreadPool::new()));
+
+        let shared = thread_pool.clone();
+        let result = thread::spawn(move || {
+            let jobs: Vec<_> = (0..2).map(|i| move || if i == 1 { panic!("Job failed") }).collect();
+            shared.lock().unwrap().scoped(jobs);
+        }).join();
+        assert!(result.is_err());
+
+        let mut thread_pool = match thread_pool.lock() {
+            Ok(thread_pool) => thread_pool,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        // the worker of the panicked job was replaced, so this call doesn't lose a job
+        let sum = AtomicUsize::new(0);
+        let jobs: Vec<_> = (1..3).map(|i| {
+            let sum = &sum;
+            move || { sum.fetch_add(i, Ordering::SeqCst); }
+        }).collect();
+        thread_pool.scoped(jobs);
+
+        assert_eq!(sum.load(Ordering::SeqCst), 3);
+    }
+}
\ No newline at end of file
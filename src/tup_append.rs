@@ -1,35 +1,99 @@
-pub trait TupAppend<T, Result> {
-    fn tup_append(self, x: T) -> Result;
+pub trait TupAppend<T> {
+    type Output;
+    fn tup_append(self, x: T) -> Self::Output;
 }
- 
-impl<A, B> TupAppend<B, (A,B)> for (A,) {
-    fn tup_append(self, x: B) -> (A, B) {
-        (self.0, x)
-    }
+
+pub trait TupPrepend<T> {
+    type Output;
+    fn tup_prepend(self, x: T) -> Self::Output;
 }
- 
-impl<A, B, C> TupAppend<C, (A,B,C)> for (A, B) {
-    fn tup_append(self, x: C) -> (A, B, C) {
-        (self.0, self.1, x)
-    }
+
+pub trait TupConcat<T> {
+    type Output;
+    fn tup_concat(self, x: T) -> Self::Output;
 }
 
-impl<A, B, C, D> TupAppend<D, (A,B,C,D)> for (A, B, C) {
-    fn tup_append(self, x: D) -> (A, B, C, D) {
-        (self.0, self.1, self.2, x)
-    }
+// Invokes $m once for every non-empty prefix of the items, e.g. for_each_tuple!(m, [], (A a), (B b))
+// expands to m!((A a)); m!((A a), (B b));
+macro_rules! for_each_tuple {
+    ($m:ident, [$($done:tt)*]) => ();
+    ($m:ident, [$($done:tt)*], $next:tt $(, $rest:tt)*) => (
+        $m!($($done,)* $next);
+        for_each_tuple!($m, [$($done)* $next] $(, $rest)*);
+    );
 }
 
-impl<A, B, C, D, E> TupAppend<E, (A,B,C,D,E)> for (A, B, C, D) {
-    fn tup_append(self, x: E) -> (A, B, C, D, E) {
-        (self.0, self.1, self.2, self.3, x)
-    }
+macro_rules! impl_tup_append {
+    ($(($T:ident $t:ident)),+) => (
+        impl<$($T,)+ Item> TupAppend<Item> for ($($T,)+) {
+            type Output = ($($T,)+ Item);
+
+            fn tup_append(self, x: Item) -> ($($T,)+ Item) {
+                let ($($t,)+) = self;
+                ($($t,)+ x)
+            }
+        }
+
+        impl<$($T,)+ Item> TupPrepend<Item> for ($($T,)+) {
+            type Output = (Item, $($T,)+);
+
+            fn tup_prepend(self, x: Item) -> (Item, $($T,)+) {
+                let ($($t,)+) = self;
+                (x, $($t,)+)
+            }
+        }
+    )
+}
+
+// Appends the first element of x, then concatenates the rest
+macro_rules! impl_tup_concat {
+    (($T:ident $t:ident) $(, ($Rest:ident $rest:ident))*) => (
+        impl<Left, $T $(, $Rest)*> TupConcat<($T, $($Rest,)*)> for Left
+            where Left: TupAppend<$T>, <Left as TupAppend<$T>>::Output: TupConcat<($($Rest,)*)>
+        {
+            type Output = <<Left as TupAppend<$T>>::Output as TupConcat<($($Rest,)*)>>::Output;
+
+            fn tup_concat(self, x: ($T, $($Rest,)*)) -> Self::Output {
+                let ($t, $($rest,)*) = x;
+                self.tup_append($t).tup_concat(($($rest,)*))
+            }
+        }
+    )
 }
 
-impl<A, B, C, D, E, F> TupAppend<F, (A,B,C,D,E,F)> for (A, B, C, D, E) {
-    fn tup_append(self, x: F) -> (A, B, C, D, E, F) {
-        (self.0, self.1, self.2, self.3, self.4, x)
+impl<Left> TupConcat<()> for Left {
+    type Output = Left;
+
+    fn tup_concat(self, _: ()) -> Left {
+        self
     }
 }
 
-// TODO possibly need longer TupAppend
\ No newline at end of file
+// tuples up to 16 elements
+for_each_tuple!(impl_tup_append, [], (A a), (B b), (C c), (D d), (E e), (F f), (G g), (H h), (I i), (J j), (K k), (L l), (M m), (N n), (O o));
+for_each_tuple!(impl_tup_concat, [], (A a), (B b), (C c), (D d), (E e), (F f), (G g), (H h), (I i), (J j), (K k), (L l), (M m), (N n), (O o));
+
+#[cfg(test)]
+mod tests {
+    use super::{ TupAppend, TupPrepend, TupConcat };
+
+    #[test]
+    fn append_prepend_concat() {
+        assert_eq!((1,).tup_append(2), (1, 2));
+        assert_eq!((2, 3).tup_prepend(1), (1, 2, 3));
+        assert_eq!((1, 2).tup_concat((3, 4, 5)), (1, 2, 3, 4, 5));
+
+        let long: (u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8) =
+            (0, 1, 2, 3, 4, 5, 6, 7).tup_concat((8, 9, 10, 11, 12, 13, 14)).tup_append(15);
+        assert_eq!(long.15, 15);
+    }
+
+    #[test]
+    fn concat_mixed_types() {
+        let concatenated = (1u8, "two").tup_concat((3.0f32, 'f', 5i64));
+        assert_eq!(concatenated, (1u8, "two", 3.0f32, 'f', 5i64));
+
+        assert_eq!((1,).tup_concat(()), (1,));
+        assert_eq!((1,).tup_concat((2,)).tup_concat((3, 4)), (1, 2, 3, 4));
+    }
+}
\ No newline at end of file
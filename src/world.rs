@@ -1,21 +1,117 @@
+use std::any::{ Any };
 use std::marker::PhantomData;
-use std::collections::{ BitVec };
-
-use entity::{ EntityManager, Entity, EntityIterator };
-use component::{ ComponentManager, ComponentList, ComponentData };
+use std::collections::{ BitVec, BTreeMap, VecMap };
+use std::fmt::{ Debug };
+use std::hash::{ Hash, Hasher, SipHasher };
+use std::cmp::{ Ordering };
+use std::iter::{ repeat };
+use std::vec::{ IntoIter };
+
+use rustc_serialize::{ Encodable, Decodable };
+use rustc_serialize::json::{ Json };
+
+use entity::{ EntityManager, Entity, EntityIterator, EntityMap, MapEntities };
+use component::{ Bundle, ComponentManager, ComponentList, ComponentData, ComponentHooks, ComponentId, ComponentInfo, ComponentSnapshot, EntityDebug, FindIterator, QueryId, QueryIterator, RemovedIterator };
 use system::{ SystemManager, System };
+use names::{ EntityNames };
+use query::{ Filter, Fetch, FilterIterator, ParFetch };
+use thread_pool::{ ThreadPool };
+use index::{ ComponentIndex, Spatial };
+use bytes::{ write_u32, write_string, read_u32, read_bytes, read_string };
 
 // TODO Add Entity Templates
 // TODO Test serialization feasibility
-// TODO figure out InvariantLifetime alternative to InvariantType
-// since InvariantType leads to code bloat due to multiple
-// monomorphizations
+// Every WorldId type leads to code bloat due to multiple monomorphizations
+// brand::scope avoids that by branding Worlds with an invariant lifetime instead
 
 pub struct World<WorldId> {
     phantom: PhantomData<WorldId>,
     entity_manager: EntityManager<WorldId>,
     system_manager: SystemManager<WorldId>,
     component_manager: ComponentManager<WorldId>,
+    entity_names: EntityNames<WorldId>,
+    thread_pool: ThreadPool,
+}
+
+// Binary save layout, all integers little endian
+// u32 save format version
+// u32 entity count, per entity: u32 component count, entities are numbered by their position in the save
+// u32 name count, per name: u32 entity, string name
+// u32 column count, per component type:
+//     string component name, u32 schema version, u32 row count, per row: u32 entity, u32 length, encoded component
+// strings are a u32 length followed by UTF-8
+const SAVE_FORMAT_VERSION: u32 = 1;
+
+struct SaveColumn {
+    name: String,
+    schema_version: u32,
+    rows: Vec<(usize, Vec<u8>)>,
+}
+
+struct SaveData {
+    // by entity position
+    component_counts: Vec<usize>,
+    names: Vec<(usize, String)>,
+    columns: Vec<SaveColumn>,
+}
+
+fn read_save_data(mut buffer: &[u8]) -> Option<SaveData> {
+    if try_read!(read_u32(&mut buffer)) != SAVE_FORMAT_VERSION {
+        return None;
+    }
+
+    let entity_count = try_read!(read_u32(&mut buffer)) as usize;
+
+    // every entity takes 4 bytes, so a corrupt count can't create more entities than fit in the save
+    if entity_count > buffer.len() / 4 {
+        return None;
+    }
+
+    let mut component_counts = Vec::with_capacity(entity_count);
+    for _ in 0..entity_count {
+        component_counts.push(try_read!(read_u32(&mut buffer)) as usize);
+    }
+
+    let name_count = try_read!(read_u32(&mut buffer));
+    let mut names = Vec::new();
+    for _ in 0..name_count {
+        let entity = try_read!(read_u32(&mut buffer)) as usize;
+        names.push((entity, try_read!(read_string(&mut buffer))));
+    }
+
+    let column_count = try_read!(read_u32(&mut buffer));
+    let mut columns = Vec::new();
+    for _ in 0..column_count {
+        let name = try_read!(read_string(&mut buffer));
+        let schema_version = try_read!(read_u32(&mut buffer));
+
+        let row_count = try_read!(read_u32(&mut buffer));
+        let mut rows = Vec::new();
+        for _ in 0..row_count {
+            let entity = try_read!(read_u32(&mut buffer)) as usize;
+            let length = try_read!(read_u32(&mut buffer)) as usize;
+            rows.push((entity, try_read!(read_bytes(&mut buffer, length)).to_vec()));
+        }
+
+        columns.push(SaveColumn {
+            name: name,
+            schema_version: schema_version,
+            rows: rows,
+        });
+    }
+
+    Some(SaveData {
+        component_counts: component_counts,
+        names: names,
+        columns: columns,
+    })
+}
+
+/// Opaque copy of the state of a World, see World::snapshot
+pub struct WorldSnapshot<WorldId> {
+    entity_manager: EntityManager<WorldId>,
+    component_snapshot: ComponentSnapshot<WorldId>,
+    entity_names: EntityNames<WorldId>,
 }
 
 impl<WorldId> World<WorldId> {
@@ -27,6 +123,8 @@ impl<WorldId> World<WorldId> {
             entity_manager: EntityManager::new(initial_capacity),
             system_manager: SystemManager::new(),
             component_manager: ComponentManager::new(initial_capacity),
+            entity_names: EntityNames::new(),
+            thread_pool: ThreadPool::new(),
         }
     }
 
@@ -38,11 +136,75 @@ impl<WorldId> World<WorldId> {
         entity
     }
 
+    /// Create an entity with all components of the bundle
+    pub fn spawn<B: Bundle>(&mut self, bundle: B) -> Entity<WorldId> {
+        let entity = self.create_entity();
+        self.component_manager.assign_bundle(&entity, bundle);
+        entity
+    }
+
     pub fn destroy_entity(&mut self, entity: Entity<WorldId>) {
         self.component_manager.entity_destroyed(&entity);
+        self.entity_names.entity_destroyed(&entity);
         self.entity_manager.destroy_entity(entity)
     }
 
+    /// Create a new entity with copies of all components registered with register_clone
+    pub fn clone_entity(&mut self, entity: &Entity<WorldId>) -> Entity<WorldId> {
+        assert!(self.is_valid(entity));
+
+        let clone = self.create_entity();
+        self.component_manager.clone_components(entity, &clone);
+        clone
+    }
+
+    /// Move entity with its components and name to another World, returning the new entity
+    /// Components registered with register_map_entities only find entity itself in the EntityMap, see MapEntities
+    /// Panics before moving anything if a component isn't registered in other
+    /// Both WorldIds must be 'static, so Worlds from brand::scope can't use it
+    pub fn move_entity_to<OtherId>(&mut self, other: &mut World<OtherId>, entity: Entity<WorldId>) -> Entity<OtherId> where WorldId: 'static, OtherId: 'static {
+        assert!(self.is_valid(&entity));
+
+        let target = other.create_entity();
+
+        let mut entity_map = EntityMap::new();
+        entity_map.insert(&entity, target.clone());
+
+        self.component_manager.move_components(&entity, &mut other.component_manager, &target, &entity_map);
+        if let Some(name) = self.entity_names.remove_name(&entity) {
+            other.entity_names.set_name(&target, &name);
+        }
+
+        self.destroy_entity(entity);
+        target
+    }
+
+    /// Move all entities of other into this World
+    /// The returned EntityMap is also used to convert components registered with register_map_entities
+    /// Both WorldIds must be 'static, so Worlds from brand::scope can't use it
+    pub fn merge<OtherId>(&mut self, other: World<OtherId>) -> EntityMap<OtherId, WorldId> where WorldId: 'static, OtherId: 'static {
+        let mut other = other;
+        let entities: Vec<Entity<OtherId>> = other.entities().collect();
+
+        // create all entities first, so components can refer to any of them
+        let mut entity_map = EntityMap::new();
+        for entity in entities.iter() {
+            let target = self.create_entity();
+            entity_map.insert(entity, target);
+        }
+
+        for entity in entities.iter() {
+            let target = entity_map.get(entity).unwrap();
+
+            other.component_manager.move_components(entity, &mut self.component_manager, &target, &entity_map);
+            if let Some(name) = other.entity_names.remove_name(entity) {
+                self.entity_names.set_name(&target, &name);
+            }
+        }
+
+        entity_map
+    }
+
     pub fn is_valid(&self, entity: &Entity<WorldId>) -> bool {
         self.entity_manager.is_valid(entity)
     }
@@ -51,6 +213,296 @@ impl<WorldId> World<WorldId> {
         self.entity_manager.entities()
     }
 
+    // *** Snapshots ***
+
+    /// Copy of entities, components and names, e.g. for rollback
+    /// Only components registered with register_clone are copied, others keep their values on restore
+    pub fn snapshot(&self) -> WorldSnapshot<WorldId> {
+        WorldSnapshot {
+            entity_manager: self.entity_manager.clone(),
+            component_snapshot: self.component_manager.snapshot(),
+            entity_names: self.entity_names.clone(),
+        }
+    }
+
+    /// Return to the state of the snapshot
+    /// Entities created since become invalid, entities destroyed since become valid again
+    /// Entities created since are destroyed first, running on_remove hooks, so components that weren't copied don't outlive them
+    pub fn restore(&mut self, snapshot: &WorldSnapshot<WorldId>) {
+        let created: Vec<Entity<WorldId>> = self.entity_manager.entities()
+            .filter(|entity| !snapshot.entity_manager.is_valid(entity))
+            .collect();
+        for entity in created.iter() {
+            self.component_manager.entity_destroyed(entity);
+        }
+
+        self.entity_manager = snapshot.entity_manager.clone();
+        self.component_manager.restore(&snapshot.component_snapshot);
+        self.entity_names = snapshot.entity_names.clone();
+    }
+
+    // *** Hashing ***
+
+    /// Hash of all entities, their component masks and components registered with register_hash
+    /// Equal on peers with equal state, e.g. to detect desyncs in lockstep multiplayer
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = SipHasher::new();
+
+        for entity in self.entities() {
+            self.component_manager.hash_entity(&entity, &mut hasher);
+        }
+
+        hasher.finish()
+    }
+
+    /// Hash of every entity in index order, to find out which entities differ after a desync
+    pub fn entity_hashes(&self) -> Vec<(Entity<WorldId>, u64)> {
+        self.entities()
+            .map(|entity| {
+                let mut hasher = SipHasher::new();
+                self.component_manager.hash_entity(&entity, &mut hasher);
+                (entity, hasher.finish())
+            })
+            .collect()
+    }
+
+    // *** JSON ***
+
+    /// Every entity with its name and components registered with register_serialize, as pretty printed JSON
+    /// {"entities": [{"index": 0, "name": "player", "components": {"Position": {"x": 1.0}}}]}
+    pub fn dump_json(&self) -> String {
+        let entities = self.entities()
+            .map(|entity| {
+                let mut components = BTreeMap::new();
+                for component_id in self.component_manager.get_entity_components(&entity).into_iter() {
+                    if let Some(json) = self.component_manager.component_to_json(&entity, component_id) {
+                        components.insert(self.component_manager.get_component_info(component_id).serialize_name.clone().unwrap(), json);
+                    }
+                }
+
+                let mut object = BTreeMap::new();
+                object.insert("index".to_string(), Json::U64(entity.index() as u64));
+                if let Some(name) = self.get_name(&entity) {
+                    object.insert("name".to_string(), Json::String(name.to_string()));
+                }
+                object.insert("components".to_string(), Json::Object(components));
+                Json::Object(object)
+            })
+            .collect();
+
+        let mut root = BTreeMap::new();
+        root.insert("entities".to_string(), Json::Array(entities));
+        format!("{}", Json::Object(root).pretty())
+    }
+
+    /// Create the entities of JSON in the format of dump_json, returning them in order
+    /// Indices in the JSON are ignored, components are found by their register_serialize name
+    /// On error, no entities are added: entities created before a component failed to decode are destroyed again,
+    /// which runs on_remove hooks and records their components as removed
+    pub fn load_json(&mut self, json: &str) -> Result<Vec<Entity<WorldId>>, String> {
+        let json = match Json::from_str(json) {
+            Ok(json) => json,
+            Err(error) => return Err(format!("Invalid JSON: {:?}", error)),
+        };
+
+        let entities_json = match json.find("entities").and_then(|entities_json| entities_json.as_array()) {
+            Some(entities_json) => entities_json,
+            None => return Err("Missing entities".to_string()),
+        };
+
+        // name and components of every entity, looked up before anything is created
+        let mut loaded = Vec::new();
+        for entity_json in entities_json.iter() {
+            let mut components = Vec::new();
+
+            if let Some(components_json) = entity_json.find("components").and_then(|components_json| components_json.as_object()) {
+                for (name, component_json) in components_json.iter() {
+                    match self.component_manager.get_component_id_by_serialize_name(name) {
+                        Some(component_id) if self.get_component_info(component_id).is_serializable() => components.push((component_id, name, component_json)),
+                        _ => return Err(format!("Unknown component {}", name)),
+                    }
+                }
+            }
+
+            loaded.push((entity_json.find("name").and_then(|name| name.as_string()), components));
+        }
+
+        // only decoding can fail from here on, which destroys the entities again
+        let entities: Vec<Entity<WorldId>> = loaded.iter().map(|_| self.create_entity()).collect();
+
+        let mut error = None;
+        for (position, &(_, ref components)) in loaded.iter().enumerate() {
+            for &(component_id, name, component_json) in components.iter() {
+                if error.is_none() && !self.component_manager.component_from_json(&entities[position], component_id, component_json.clone()) {
+                    error = Some(format!("Can't load component {} of entity {}", name, position));
+                }
+            }
+        }
+
+        if let Some(error) = error {
+            for entity in entities.into_iter() {
+                self.destroy_entity(entity);
+            }
+            return Err(error);
+        }
+
+        // last, since set_name takes names away from other entities
+        for (position, &(name, _)) in loaded.iter().enumerate() {
+            if let Some(name) = name {
+                self.set_name(&entities[position], name);
+            }
+        }
+
+        Ok(entities)
+    }
+
+    // *** Binary saves ***
+
+    /// Every entity with its name and components registered with register_serialize
+    /// Components are stored by their register_serialize name with their schema version, see register_schema
+    pub fn save_binary(&self) -> Vec<u8> {
+        let mut positions = VecMap::new();
+        for (position, entity) in self.entities().enumerate() {
+            positions.insert(entity.index(), position as u32);
+        }
+
+        let mut buffer = Vec::new();
+        write_u32(&mut buffer, SAVE_FORMAT_VERSION);
+        write_u32(&mut buffer, positions.len() as u32);
+        for entity in self.entities() {
+            let component_count = self.component_manager.get_entity_components(&entity).into_iter()
+                .filter(|&component_id| self.get_component_info(component_id).is_serializable())
+                .count();
+            write_u32(&mut buffer, component_count as u32);
+        }
+
+        write_u32(&mut buffer, self.entity_names.len() as u32);
+        for (name, entity) in self.entity_names.iter() {
+            write_u32(&mut buffer, positions[entity.index()]);
+            write_string(&mut buffer, name);
+        }
+
+        let component_ids: Vec<ComponentId> = (0..self.get_components_length())
+            .map(|index| ComponentId(index))
+            .filter(|&component_id| self.get_component_info(component_id).is_serializable())
+            .collect();
+
+        write_u32(&mut buffer, component_ids.len() as u32);
+        for &component_id in component_ids.iter() {
+            let component_info = self.get_component_info(component_id);
+            write_string(&mut buffer, component_info.serialize_name.as_ref().unwrap());
+            write_u32(&mut buffer, component_info.schema_version);
+
+            let rows: Vec<(u32, Vec<u8>)> = self.entities()
+                .filter_map(|entity| self.component_manager.encode_component(&entity, component_id)
+                    .map(|bytes| (positions[entity.index()], bytes)))
+                .collect();
+
+            write_u32(&mut buffer, rows.len() as u32);
+            for (position, bytes) in rows.into_iter() {
+                write_u32(&mut buffer, position);
+                write_u32(&mut buffer, bytes.len() as u32);
+                buffer.push_all(&bytes);
+            }
+        }
+
+        buffer
+    }
+
+    /// Create the entities of a save_binary save, returning them in order
+    /// Components saved with an older schema version are converted with their registered migration
+    /// On error, no entities are added, like load_json
+    pub fn load_binary(&mut self, bytes: &[u8]) -> Result<Vec<Entity<WorldId>>, String> {
+        let save_data = match read_save_data(bytes) {
+            Some(save_data) => save_data,
+            None => return Err("Invalid save".to_string()),
+        };
+
+        let entity_count = save_data.component_counts.len();
+
+        for &(position, ref name) in save_data.names.iter() {
+            if position >= entity_count {
+                return Err(format!("Name {} of unknown entity {}", name, position));
+            }
+        }
+
+        let mut component_counts: Vec<usize> = repeat(0).take(entity_count).collect();
+        let mut component_ids = Vec::new();
+        for column in save_data.columns.iter() {
+            let component_id = match self.component_manager.get_component_id_by_serialize_name(&column.name) {
+                Some(component_id) if self.get_component_info(component_id).is_serializable() => component_id,
+                _ => return Err(format!("Unknown component {}", column.name)),
+            };
+
+            for &(position, _) in column.rows.iter() {
+                if position >= entity_count {
+                    return Err(format!("Component {} of unknown entity {}", column.name, position));
+                }
+
+                component_counts[position] += 1;
+            }
+
+            component_ids.push(component_id);
+        }
+
+        if component_counts != save_data.component_counts {
+            return Err("Invalid save".to_string());
+        }
+
+        // only decoding can fail from here on, which destroys the entities again
+        let entities: Vec<Entity<WorldId>> = (0..entity_count).map(|_| self.create_entity()).collect();
+
+        if let Err(error) = self.decode_save_columns(&save_data, &component_ids[..], &entities[..]) {
+            for entity in entities.into_iter() {
+                self.destroy_entity(entity);
+            }
+            return Err(error);
+        }
+
+        // last, since set_name takes names away from other entities
+        for &(position, ref name) in save_data.names.iter() {
+            self.set_name(&entities[position], name);
+        }
+
+        Ok(entities)
+    }
+
+    fn decode_save_columns(&mut self, save_data: &SaveData, component_ids: &[ComponentId], entities: &[Entity<WorldId>]) -> Result<(), String> {
+        for (column, &component_id) in save_data.columns.iter().zip(component_ids.iter()) {
+            for &(position, ref bytes) in column.rows.iter() {
+                if !self.component_manager.decode_component_version(&entities[position], component_id, column.schema_version, bytes) {
+                    return Err(format!("Can't load component {} version {} of entity {}", column.name, column.schema_version, position));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // *** EntityNames ***
+
+    pub fn set_name(&mut self, entity: &Entity<WorldId>, name: &str) {
+        assert!(self.is_valid(entity));
+
+        self.entity_names.set_name(entity, name)
+    }
+
+    pub fn remove_name(&mut self, entity: &Entity<WorldId>) -> Option<String> {
+        self.entity_names.remove_name(entity)
+    }
+
+    pub fn get_name(&self, entity: &Entity<WorldId>) -> Option<&str> {
+        self.entity_names.get_name(entity)
+    }
+
+    pub fn get_named_entity(&self, name: &str) -> Option<Entity<WorldId>> {
+        self.entity_names.get_entity(name)
+    }
+
+    pub fn entity_names(&self) -> &EntityNames<WorldId> {
+        &self.entity_names
+    }
+
     // *** ComponentManager ***
 
     pub fn register_component<C: 'static>(&mut self, component_list: Box<ComponentList<C> + 'static>) {
@@ -63,6 +515,18 @@ impl<WorldId> World<WorldId> {
         self.component_manager.assign_component(entity, component)
     }
 
+    pub fn assign_bundle<B: Bundle>(&mut self, entity: &Entity<WorldId>, bundle: B) {
+        assert!(self.is_valid(entity));
+
+        self.component_manager.assign_bundle(entity, bundle)
+    }
+
+    pub fn remove_component<C: 'static>(&mut self, entity: &Entity<WorldId>) -> bool {
+        assert!(self.is_valid(entity));
+
+        self.component_manager.remove_component::<C>(entity)
+    }
+
     pub fn has_component<C: 'static>(&self, entity: &Entity<WorldId>) -> bool {
         assert!(self.is_valid(entity));
 
@@ -81,6 +545,65 @@ impl<WorldId> World<WorldId> {
         self.component_manager.get_component_mut::<C>(entity)
     }
 
+    pub fn filter<'a, Q, F>(&'a self) -> FilterIterator<'a, WorldId, Q> where Q: Fetch<'a, WorldId>, F: Filter {
+        self.component_manager.filter::<Q, F>(&self.entity_manager)
+    }
+
+    pub fn find<'a, C: PartialEq + 'static>(&'a self, value: &'a C) -> FindIterator<'a, WorldId, C> {
+        self.component_manager.find(&self.entity_manager, value)
+    }
+
+    pub fn register_component_index<C: 'static>(&mut self, index: Box<ComponentIndex<C> + 'static>) {
+        self.component_manager.register_component_index::<C>(index)
+    }
+
+    pub fn register_index<C: 'static, K: Hash + Eq + Clone + 'static>(&mut self, key: fn(&C) -> K) {
+        self.component_manager.register_index::<C, K>(key)
+    }
+
+    pub fn find_by<C: 'static, K: Hash + Eq + Clone + 'static>(&self, key: &K) -> IntoIter<Entity<WorldId>> {
+        self.component_manager.find_by::<C, K>(&self.entity_manager, key)
+    }
+
+    pub fn register_spatial_index<C: Spatial + 'static>(&mut self, cell_size: f32) {
+        self.component_manager.register_spatial_index::<C>(cell_size)
+    }
+
+    pub fn find_within_radius<C: Spatial + 'static>(&self, center: (f32, f32), radius: f32) -> IntoIter<Entity<WorldId>> {
+        self.component_manager.find_within_radius::<C>(&self.entity_manager, center, radius)
+    }
+
+    pub fn find_within_aabb<C: Spatial + 'static>(&self, min: (f32, f32), max: (f32, f32)) -> IntoIter<Entity<WorldId>> {
+        self.component_manager.find_within_aabb::<C>(&self.entity_manager, min, max)
+    }
+
+    /// Worker threads are kept by the World, so calling this every frame doesn't start threads
+    pub fn par_for_each<'a, Q, F>(&'a mut self, query_id: QueryId, threads: usize, f: F)
+        where Q: ParFetch<'a, WorldId>, F: Fn(Entity<WorldId>, Q::Item) + Sync
+    {
+        self.component_manager.par_for_each::<Q, F>(&self.entity_manager, &mut self.thread_pool, query_id, threads, f)
+    }
+
+    pub fn sort_components<C: 'static, F>(&mut self, compare: F) -> bool where F: Fn(&C, &C) -> Ordering {
+        self.component_manager.sort_components::<C, F>(compare)
+    }
+
+    pub fn entities_in_order<C: 'static>(&self) -> IntoIter<Entity<WorldId>> {
+        self.component_manager.entities_in_order::<C>(&self.entity_manager)
+    }
+
+    pub fn sorted<C: 'static, F>(&self, compare: F) -> IntoIter<Entity<WorldId>> where F: Fn(&C, &C) -> Ordering {
+        self.component_manager.sorted::<C, F>(&self.entity_manager, compare)
+    }
+
+    pub fn register_query(&mut self, with: &[ComponentId], without: &[ComponentId]) -> QueryId {
+        self.component_manager.register_query(with, without)
+    }
+
+    pub fn query_entities(&self, query_id: QueryId) -> QueryIterator<WorldId> {
+        self.component_manager.query_entities(&self.entity_manager, query_id)
+    }
+
     pub fn get_component_data<C: 'static>(&self) -> &ComponentData<C> {
         self.component_manager.get_component_data::<C>()
     }
@@ -97,6 +620,177 @@ impl<WorldId> World<WorldId> {
         self.component_manager.get_components_length()
     }
 
+    pub fn get_component_id<C: 'static>(&self) -> ComponentId {
+        self.component_manager.get_component_id::<C>()
+    }
+
+    pub fn remove_component_id(&mut self, entity: &Entity<WorldId>, component_id: ComponentId) -> bool {
+        assert!(self.is_valid(entity));
+
+        self.component_manager.remove_component_id(entity, component_id)
+    }
+
+    pub fn remove_component_ids(&mut self, entity: &Entity<WorldId>, component_ids: &[ComponentId]) -> bool {
+        assert!(self.is_valid(entity));
+
+        self.component_manager.remove_component_ids(entity, component_ids)
+    }
+
+    pub fn has_component_id(&self, entity: &Entity<WorldId>, component_id: ComponentId) -> bool {
+        assert!(self.is_valid(entity));
+
+        self.component_manager.has_component_id(entity, component_id)
+    }
+
+    pub fn register_hooks<C: ComponentHooks<WorldId> + Clone>(&mut self) {
+        self.component_manager.register_hooks::<C>()
+    }
+
+    pub fn register_required<C: 'static, Required: Default + 'static>(&mut self) {
+        self.component_manager.register_required::<C, Required>(&self.entity_manager)
+    }
+
+    pub fn register_debug<C: Debug + 'static>(&mut self) {
+        self.component_manager.register_debug::<C>()
+    }
+
+    pub fn register_clone<C: Clone + 'static>(&mut self) {
+        self.component_manager.register_clone::<C>()
+    }
+
+    pub fn register_hash<C: Hash + 'static>(&mut self) {
+        self.component_manager.register_hash::<C>()
+    }
+
+    pub fn register_serialize<C: Encodable + Decodable + 'static>(&mut self, name: &str) {
+        self.component_manager.register_serialize::<C>(name)
+    }
+
+    pub fn register_schema<C: 'static>(&mut self, version: u32) {
+        self.component_manager.register_schema::<C>(version)
+    }
+
+    pub fn register_migration<C, Old, F>(&mut self, from_version: u32, migration: F)
+        where C: 'static, Old: Decodable, F: Fn(Old) -> C + 'static
+    {
+        self.component_manager.register_migration::<C, Old, F>(from_version, migration)
+    }
+
+    pub fn encode_component(&self, entity: &Entity<WorldId>, component_id: ComponentId) -> Option<Vec<u8>> {
+        assert!(self.is_valid(entity));
+
+        self.component_manager.encode_component(entity, component_id)
+    }
+
+    pub fn decode_component(&mut self, entity: &Entity<WorldId>, component_id: ComponentId, bytes: &[u8]) -> bool {
+        assert!(self.is_valid(entity));
+
+        self.component_manager.decode_component(entity, component_id, bytes)
+    }
+
+    pub fn get_component_id_by_serialize_name(&self, name: &str) -> Option<ComponentId> {
+        self.component_manager.get_component_id_by_serialize_name(name)
+    }
+
+    pub fn can_decode_component(&self, component_id: ComponentId, bytes: &[u8]) -> bool {
+        self.component_manager.can_decode_component(component_id, bytes)
+    }
+
+    /// Both WorldIds must be 'static, so Worlds from brand::scope can't use it
+    pub fn register_map_entities<C, OtherId>(&mut self) where C: MapEntities<WorldId, OtherId> + 'static, WorldId: 'static, OtherId: 'static {
+        self.component_manager.register_map_entities::<C, OtherId>()
+    }
+
+    pub fn get_component_info(&self, component_id: ComponentId) -> &ComponentInfo<WorldId> {
+        self.component_manager.get_component_info(component_id)
+    }
+
+    pub fn get_component_infos(&self) -> &[ComponentInfo<WorldId>] {
+        self.component_manager.get_component_infos()
+    }
+
+    pub fn get_entity_components(&self, entity: &Entity<WorldId>) -> Vec<ComponentId> {
+        assert!(self.is_valid(entity));
+
+        self.component_manager.get_entity_components(entity)
+    }
+
+    pub fn debug_entity<'a>(&'a self, entity: &'a Entity<WorldId>) -> EntityDebug<'a, WorldId> {
+        assert!(self.is_valid(entity));
+
+        self.component_manager.debug_entity(entity)
+    }
+
+    pub fn register_dynamic_component(&mut self, name: &str) -> ComponentId {
+        self.component_manager.register_dynamic_component(name)
+    }
+
+    pub fn get_dynamic_component_id(&self, name: &str) -> Option<ComponentId> {
+        self.component_manager.get_dynamic_component_id(name)
+    }
+
+    pub fn assign_dynamic_component(&mut self, entity: &Entity<WorldId>, component_id: ComponentId, component: Box<Any>) {
+        assert!(self.is_valid(entity));
+
+        self.component_manager.assign_dynamic_component(entity, component_id, component)
+    }
+
+    pub fn remove_dynamic_component(&mut self, entity: &Entity<WorldId>, component_id: ComponentId) {
+        assert!(self.is_valid(entity));
+
+        self.component_manager.remove_dynamic_component(entity, component_id)
+    }
+
+    pub fn get_dynamic_component(&self, entity: &Entity<WorldId>, component_id: ComponentId) -> Option<&Any> {
+        assert!(self.is_valid(entity));
+
+        self.component_manager.get_dynamic_component(entity, component_id)
+    }
+
+    pub fn get_dynamic_component_mut(&mut self, entity: &Entity<WorldId>, component_id: ComponentId) -> Option<&mut Any> {
+        assert!(self.is_valid(entity));
+
+        self.component_manager.get_dynamic_component_mut(entity, component_id)
+    }
+
+    pub fn removed<C: 'static>(&self) -> RemovedIterator<WorldId, C> {
+        self.component_manager.removed::<C>()
+    }
+
+    pub fn removed_since<C: 'static>(&self, tick: usize) -> RemovedIterator<WorldId, C> {
+        self.component_manager.removed_since::<C>(tick)
+    }
+
+    pub fn clear_removed(&mut self) {
+        self.component_manager.clear_removed()
+    }
+
+    pub fn tick(&self) -> usize {
+        self.component_manager.tick()
+    }
+
+    pub fn increment_tick(&mut self) -> usize {
+        self.component_manager.increment_tick()
+    }
+
+    pub fn is_added_since<C: 'static>(&self, entity: &Entity<WorldId>, tick: usize) -> bool {
+        assert!(self.is_valid(entity));
+
+        self.component_manager.is_added_since::<C>(entity, tick)
+    }
+
+    pub fn is_changed_since<C: 'static>(&self, entity: &Entity<WorldId>, tick: usize) -> bool {
+        assert!(self.is_valid(entity));
+
+        self.component_manager.is_changed_since::<C>(entity, tick)
+    }
+
+    pub fn is_changed_since_id(&self, entity: &Entity<WorldId>, component_id: ComponentId, tick: usize) -> bool {
+        assert!(self.is_valid(entity));
+
+        self.component_manager.is_changed_since_id(entity, component_id, tick)
+    }
+
     // *** SystemManager ***
 
     pub fn register_system<S>(&mut self, system: S) where S: System<WorldId, S> + 'static {
@@ -104,19 +798,24 @@ impl<WorldId> World<WorldId> {
     }
 
     pub fn update_system<A, S>(&mut self, args: &A) where S: System<WorldId, S> + 'static {
-        self.system_manager.update::<A,S>(&mut self.entity_manager, &mut self.component_manager, args)
+        self.system_manager.update::<A,S>(&mut self.entity_manager, &mut self.component_manager, &mut self.entity_names, args)
     }
 }
 
-// TODO allow with Player(1) style queries.
-
 #[cfg(test)]
 mod tests {
     use test::Bencher;
+    use std::cell::{ Cell };
     use std::collections::{ VecMap };
+    use std::rc::{ Rc };
     use super::{ World };
+    use entity::{ EntityManager, Entity, EntityMap, MapEntities };
+    use component::{ ComponentManager };
+    use control::{ Control };
+    use system::{ System };
 
     struct WorldId1;
+    struct WorldId2;
     struct Cmp1;
 
     #[test]
@@ -147,6 +846,449 @@ mod tests {
         world.destroy_entity(entity);
     }
 
+    #[test]
+    fn destroy_named_entity() {
+        let mut world:World<WorldId1> = World::new();
+
+        let entity = world.create_entity();
+        world.set_name(&entity, "player");
+        assert_eq!(world.get_named_entity("player"), Some(entity.clone()));
+
+        world.destroy_entity(entity);
+
+        let recycled = world.create_entity();
+        assert_eq!(world.get_named_entity("player"), None);
+        assert_eq!(world.get_name(&recycled), None);
+    }
+
+    #[test]
+    fn clone_entity() {
+        #[derive(Clone, PartialEq, Debug)]
+        struct Position(isize, isize);
+
+        let mut world:World<WorldId1> = World::new();
+
+        world.register_component::<Position>(Box::new(VecMap::new()));
+        world.register_clone::<Position>();
+
+        let entity = world.create_entity();
+        world.assign_component(&entity, Position(1, 2));
+
+        let clone = world.clone_entity(&entity);
+        assert!(clone != entity);
+        assert_eq!(world.get_component::<Position>(&clone), Some(&Position(1, 2)));
+    }
+
+    #[test]
+    fn move_entity_to() {
+        #[derive(PartialEq, Debug)]
+        struct Position(isize, isize);
+
+        let mut world1:World<WorldId1> = World::new();
+        let mut world2:World<WorldId2> = World::new();
+
+        world1.register_component::<Position>(Box::new(VecMap::new()));
+        world2.register_component::<Position>(Box::new(VecMap::new()));
+
+        let entity = world1.create_entity();
+        world1.assign_component(&entity, Position(1, 2));
+        world1.set_name(&entity, "player");
+
+        world2.create_entity();
+
+        let moved = world1.move_entity_to(&mut world2, entity.clone());
+        assert!(!world1.is_valid(&entity));
+        assert_eq!(world2.get_component::<Position>(&moved), Some(&Position(1, 2)));
+        assert_eq!(world2.get_named_entity("player"), Some(moved));
+    }
+
+    #[test]
+    fn move_entity_to_with_references() {
+        struct Target<WorldId>(Option<Entity<WorldId>>);
+
+        impl<From, To: 'static> MapEntities<From, To> for Target<From> {
+            type Output = Target<To>;
+
+            // the target may stay behind
+            fn map_entities(self, entity_map: &EntityMap<From, To>) -> Target<To> {
+                Target(self.0.and_then(|entity| entity_map.get(&entity)))
+            }
+        }
+
+        let mut world1:World<WorldId1> = World::new();
+        let mut world2:World<WorldId2> = World::new();
+
+        world1.register_component::<Target<WorldId1>>(Box::new(VecMap::new()));
+        world1.register_map_entities::<Target<WorldId1>, WorldId2>();
+        world2.register_component::<Target<WorldId2>>(Box::new(VecMap::new()));
+
+        let staying = world1.create_entity();
+        let entity1 = world1.create_entity();
+        world1.assign_component(&entity1, Target(Some(staying.clone())));
+        let entity2 = world1.create_entity();
+        world1.assign_component(&entity2, Target(Some(entity2.clone())));
+
+        let moved1 = world1.move_entity_to(&mut world2, entity1);
+        assert!(world2.get_component::<Target<WorldId2>>(&moved1).unwrap().0.is_none());
+
+        let moved2 = world1.move_entity_to(&mut world2, entity2);
+        assert_eq!(world2.get_component::<Target<WorldId2>>(&moved2).unwrap().0, Some(moved2.clone()));
+    }
+
+    #[test]
+    #[should_fail]
+    fn move_entity_to_unregistered() {
+        struct Velocity;
+
+        let mut world1:World<WorldId1> = World::new();
+        let mut world2:World<WorldId2> = World::new();
+
+        world1.register_component::<Velocity>(Box::new(VecMap::new()));
+
+        let entity = world1.create_entity();
+        world1.assign_component(&entity, Velocity);
+        world1.move_entity_to(&mut world2, entity);
+    }
+
+    #[test]
+    fn merge() {
+        #[derive(PartialEq, Debug)]
+        struct Position(isize, isize);
+
+        struct Owner<WorldId>(Entity<WorldId>);
+
+        impl<From, To: 'static> MapEntities<From, To> for Owner<From> {
+            type Output = Owner<To>;
+
+            fn map_entities(self, entity_map: &EntityMap<From, To>) -> Owner<To> {
+                Owner(entity_map.get(&self.0).unwrap())
+            }
+        }
+
+        let mut staging:World<WorldId1> = World::new();
+        let mut world:World<WorldId2> = World::new();
+
+        staging.register_component::<Position>(Box::new(VecMap::new()));
+        staging.register_component::<Owner<WorldId1>>(Box::new(VecMap::new()));
+        staging.register_map_entities::<Owner<WorldId1>, WorldId2>();
+        world.register_component::<Position>(Box::new(VecMap::new()));
+        world.register_component::<Owner<WorldId2>>(Box::new(VecMap::new()));
+
+        world.create_entity();
+
+        let owner = staging.create_entity();
+        staging.assign_component(&owner, Position(1, 2));
+        let owned = staging.create_entity();
+        staging.assign_component(&owned, Owner(owner.clone()));
+
+        let entity_map = world.merge(staging);
+        assert_eq!(entity_map.len(), 2);
+
+        let owner = entity_map.get(&owner).unwrap();
+        let owned = entity_map.get(&owned).unwrap();
+        assert_eq!(world.get_component::<Position>(&owner), Some(&Position(1, 2)));
+        assert_eq!(world.get_component::<Owner<WorldId2>>(&owned).unwrap().0, owner);
+        assert_eq!(world.entities().count(), 3);
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        #[derive(Clone, PartialEq, Debug)]
+        struct Position(isize, isize);
+
+        let mut world:World<WorldId1> = World::new();
+
+        world.register_component::<Position>(Box::new(VecMap::new()));
+        world.register_clone::<Position>();
+
+        let entity1 = world.create_entity();
+        world.assign_component(&entity1, Position(1, 2));
+
+        let snapshot = world.snapshot();
+
+        world.get_component_mut::<Position>(&entity1).unwrap().0 = 3;
+        let entity2 = world.create_entity();
+        world.assign_component(&entity2, Position(4, 5));
+
+        world.restore(&snapshot);
+        assert!(!world.is_valid(&entity2));
+        assert_eq!(world.get_component::<Position>(&entity1), Some(&Position(1, 2)));
+
+        world.destroy_entity(entity1.clone());
+
+        world.restore(&snapshot);
+        assert!(world.is_valid(&entity1));
+        assert_eq!(world.get_component::<Position>(&entity1), Some(&Position(1, 2)));
+    }
+
+    #[test]
+    fn snapshot_restore_without_register_clone() {
+        let mut world:World<WorldId1> = World::new();
+
+        world.register_component::<Cmp1>(Box::new(VecMap::new()));
+
+        let entity1 = world.create_entity();
+        let snapshot = world.snapshot();
+
+        world.assign_component(&entity1, Cmp1);
+        let entity2 = world.create_entity();
+        world.assign_component(&entity2, Cmp1);
+
+        world.restore(&snapshot);
+        assert!(world.has_component::<Cmp1>(&entity1));
+
+        let entity3 = world.create_entity();
+        assert_eq!(entity3.index(), entity2.index());
+        assert!(!world.has_component::<Cmp1>(&entity3));
+    }
+
+    #[test]
+    fn state_hash() {
+        #[derive(Hash)]
+        struct Position(i32, i32);
+
+        let mut world1:World<WorldId1> = World::new();
+        let mut world2:World<WorldId2> = World::new();
+
+        world1.register_component::<Position>(Box::new(VecMap::new()));
+        world1.register_hash::<Position>();
+        world2.register_component::<Position>(Box::new(VecMap::new()));
+        world2.register_hash::<Position>();
+
+        for i in 0..3 {
+            let entity = world1.create_entity();
+            world1.assign_component(&entity, Position(i, i));
+            let entity = world2.create_entity();
+            world2.assign_component(&entity, Position(i, i));
+        }
+
+        assert_eq!(world1.state_hash(), world2.state_hash());
+
+        let desynced = world2.entities().nth(1).unwrap();
+        world2.get_component_mut::<Position>(&desynced).unwrap().0 = 10;
+        assert!(world1.state_hash() != world2.state_hash());
+
+        let differing: Vec<usize> = world1.entity_hashes().into_iter()
+            .zip(world2.entity_hashes().into_iter())
+            .filter(|&((_, hash1), (_, hash2))| hash1 != hash2)
+            .map(|((entity, _), _)| entity.index())
+            .collect();
+        assert_eq!(differing, vec![desynced.index()]);
+    }
+
+    #[derive(RustcEncodable, RustcDecodable, PartialEq, Debug)]
+    struct Position {
+        x: isize,
+        y: isize,
+    }
+
+    #[test]
+    fn dump_load_json() {
+        let mut world1:World<WorldId1> = World::new();
+        world1.register_component::<Position>(Box::new(VecMap::new()));
+        world1.register_serialize::<Position>("Position");
+        world1.register_component::<Cmp1>(Box::new(VecMap::new()));
+
+        let entity = world1.create_entity();
+        world1.assign_component(&entity, Position { x: 1, y: 2 });
+        world1.assign_component(&entity, Cmp1);
+        world1.set_name(&entity, "player");
+
+        let mut world2:World<WorldId2> = World::new();
+        world2.register_component::<Position>(Box::new(VecMap::new()));
+        world2.register_serialize::<Position>("Position");
+
+        // the save name doesn't replace the type name
+        let position = world1.get_component_id::<Position>();
+        assert!(world1.get_component_info(position).name.ends_with("::Position"));
+        assert_eq!(world1.get_component_info(position).serialize_name, Some("Position".to_string()));
+
+        let entities = world2.load_json(&world1.dump_json()).unwrap();
+        assert_eq!(entities.len(), 1);
+        assert_eq!(world2.get_component::<Position>(&entities[0]), Some(&Position { x: 1, y: 2 }));
+        assert_eq!(world2.get_named_entity("player"), Some(entities[0].clone()));
+    }
+
+    #[test]
+    fn load_hand_edited_json() {
+        let mut world:World<WorldId1> = World::new();
+        world.register_component::<Position>(Box::new(VecMap::new()));
+        world.register_serialize::<Position>("Position");
+
+        let json = r#"{"entities": [{"components": {"Position": {"x": 3, "y": 4}}}, {}]}"#;
+
+        let entities = world.load_json(json).unwrap();
+        assert_eq!(world.get_component::<Position>(&entities[0]), Some(&Position { x: 3, y: 4 }));
+        assert!(!world.has_component::<Position>(&entities[1]));
+
+        assert!(world.load_json(r#"{"entities": [{"components": {"Unknown": {}}}]}"#).is_err());
+
+        // the first entity loads, the second doesn't
+        assert!(world.load_json(r#"{"entities": [{"components": {"Position": {"x": 5, "y": 6}}}, {"components": {"Position": {"x": "a"}}}]}"#).is_err());
+        assert_eq!(world.entities().count(), 2);
+    }
+
+    #[test]
+    fn save_load_binary() {
+        let mut world1:World<WorldId1> = World::new();
+        world1.register_component::<Position>(Box::new(VecMap::new()));
+        world1.register_serialize::<Position>("Position");
+
+        let entity = world1.create_entity();
+        world1.assign_component(&entity, Position { x: 1, y: 2 });
+        world1.create_entity();
+        world1.set_name(&entity, "player");
+
+        let mut world2:World<WorldId2> = World::new();
+        world2.register_component::<Position>(Box::new(VecMap::new()));
+        world2.register_serialize::<Position>("Position");
+
+        let entities = world2.load_binary(&world1.save_binary()).unwrap();
+        assert_eq!(entities.len(), 2);
+        assert_eq!(world2.get_component::<Position>(&entities[0]), Some(&Position { x: 1, y: 2 }));
+        assert!(!world2.has_component::<Position>(&entities[1]));
+        assert_eq!(world2.get_named_entity("player"), Some(entities[0].clone()));
+
+        assert!(world2.load_binary(&[1, 2, 3]).is_err());
+
+        // entity count way beyond the save
+        assert!(world2.load_binary(&[1, 0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        assert_eq!(world2.entities().count(), 2);
+    }
+
+    #[test]
+    fn load_binary_migrates_old_schema() {
+        #[derive(RustcEncodable, RustcDecodable)]
+        struct OldPosition {
+            x: isize,
+        }
+
+        let mut world1:World<WorldId1> = World::new();
+        world1.register_component::<OldPosition>(Box::new(VecMap::new()));
+        world1.register_serialize::<OldPosition>("Position");
+        world1.register_schema::<OldPosition>(1);
+
+        let entity = world1.create_entity();
+        world1.assign_component(&entity, OldPosition { x: 3 });
+
+        let mut world2:World<WorldId2> = World::new();
+        world2.register_component::<Position>(Box::new(VecMap::new()));
+        world2.register_serialize::<Position>("Position");
+        world2.register_schema::<Position>(2);
+
+        assert!(world2.load_binary(&world1.save_binary()).is_err());
+        assert_eq!(world2.entities().count(), 0);
+
+        world2.register_migration::<Position, OldPosition, _>(1, |old: OldPosition| Position { x: old.x, y: 0 });
+        let entities = world2.load_binary(&world1.save_binary()).unwrap();
+        assert_eq!(world2.get_component::<Position>(&entities[0]), Some(&Position { x: 3, y: 0 }));
+    }
+
+    #[test]
+    fn spawn_bundle() {
+        let mut world:World<WorldId1> = World::new();
+        world.register_component::<Position>(Box::new(VecMap::new()));
+        world.register_component::<Cmp1>(Box::new(VecMap::new()));
+
+        let entity = world.spawn((Position { x: 1, y: 2 }, Cmp1));
+        assert_eq!(world.get_component::<Position>(&entity), Some(&Position { x: 1, y: 2 }));
+        assert!(world.has_component::<Cmp1>(&entity));
+    }
+
+    #[test]
+    fn system_sees_changes_since_last_run() {
+        struct ChangedSystem(Rc<Cell<usize>>);
+
+        impl<WorldId> System<WorldId, ChangedSystem> for ChangedSystem {
+            fn update<A>(&mut self, entity_manager: &EntityManager<WorldId>, component_manager: &ComponentManager<WorldId>, control: &mut Control<WorldId, ChangedSystem>, _: &A) {
+                self.0.set(component_manager.changed::<Cmp1>(entity_manager, control.last_run()).count());
+            }
+        }
+
+        let changed = Rc::new(Cell::new(0usize));
+        let mut world:World<WorldId1> = World::new();
+
+        world.register_system(ChangedSystem(changed.clone()));
+        world.register_component::<Cmp1>(Box::new(VecMap::new()));
+
+        let entity1 = world.create_entity();
+        world.assign_component(&entity1, Cmp1);
+        let entity2 = world.create_entity();
+        world.assign_component(&entity2, Cmp1);
+
+        world.update_system::<(), ChangedSystem>(&());
+        assert_eq!(changed.get(), 2);
+
+        world.update_system::<(), ChangedSystem>(&());
+        assert_eq!(changed.get(), 0);
+
+        world.get_component_mut::<Cmp1>(&entity2);
+        world.update_system::<(), ChangedSystem>(&());
+        assert_eq!(changed.get(), 1);
+    }
+
+    #[test]
+    fn system_destroys_named_entity() {
+        struct DestroySystem(Entity<WorldId1>);
+
+        impl System<WorldId1, DestroySystem> for DestroySystem {
+            fn update<A>(&mut self, _: &EntityManager<WorldId1>, _: &ComponentManager<WorldId1>, control: &mut Control<WorldId1, DestroySystem>, _: &A) {
+                control.destroy(self.0.clone());
+            }
+        }
+
+        let mut world:World<WorldId1> = World::new();
+
+        let entity = world.create_entity();
+        world.set_name(&entity, "player");
+
+        world.register_system(DestroySystem(entity.clone()));
+        world.update_system::<(), DestroySystem>(&());
+
+        assert!(!world.is_valid(&entity));
+        assert_eq!(world.get_named_entity("player"), None);
+        assert_eq!(world.entity_names().len(), 0);
+    }
+
+    #[test]
+    fn systems_see_removed_once() {
+        struct RemovedSystem(Rc<Cell<usize>>);
+        struct OtherSystem;
+
+        impl<WorldId> System<WorldId, RemovedSystem> for RemovedSystem {
+            fn update<A>(&mut self, _: &EntityManager<WorldId>, component_manager: &ComponentManager<WorldId>, control: &mut Control<WorldId, RemovedSystem>, _: &A) {
+                self.0.set(component_manager.removed_since::<Cmp1>(control.last_run()).count());
+            }
+        }
+
+        impl<WorldId> System<WorldId, OtherSystem> for OtherSystem {
+            fn update<A>(&mut self, _: &EntityManager<WorldId>, _: &ComponentManager<WorldId>, _: &mut Control<WorldId, OtherSystem>, _: &A) {
+            }
+        }
+
+        let removed = Rc::new(Cell::new(0usize));
+        let mut world:World<WorldId1> = World::new();
+
+        world.register_system(RemovedSystem(removed.clone()));
+        world.register_system(OtherSystem);
+        world.register_component::<Cmp1>(Box::new(VecMap::new()));
+
+        let entity = world.create_entity();
+        world.assign_component(&entity, Cmp1);
+        world.remove_component::<Cmp1>(&entity);
+
+        world.update_system::<(), RemovedSystem>(&());
+        assert_eq!(removed.get(), 1);
+        // OtherSystem hasn't run yet
+        assert_eq!(world.removed::<Cmp1>().count(), 1);
+
+        world.update_system::<(), OtherSystem>(&());
+        assert_eq!(world.removed::<Cmp1>().count(), 0);
+
+        world.update_system::<(), RemovedSystem>(&());
+        assert_eq!(removed.get(), 0);
+    }
+
     #[bench]
     fn bench_create_entity(bencher: &mut Bencher) {
         struct WorldId1;
@@ -299,4 +1441,60 @@ mod tests {
             }
         });
     }
+
+    #[bench]
+    fn bench_snapshot_10k_entities_with_2_components(bencher: &mut Bencher) {
+        struct WorldId1;
+
+        #[derive(Clone)]
+        struct Cmp1(usize);
+        #[derive(Clone)]
+        struct Cmp2(usize);
+
+        let mut world: World<WorldId1> = World::new();
+
+        world.register_component::<Cmp1>(Box::new(VecMap::new()));
+        world.register_clone::<Cmp1>();
+        world.register_component::<Cmp2>(Box::new(VecMap::new()));
+        world.register_clone::<Cmp2>();
+
+        for i in range(0usize, 10_000usize) {
+            let entity = world.create_entity();
+            world.assign_component(&entity, Cmp1(i));
+            world.assign_component(&entity, Cmp2(i));
+        }
+
+        bencher.iter(|| {
+            world.snapshot()
+        });
+    }
+
+    #[bench]
+    fn bench_restore_10k_entities_with_2_components(bencher: &mut Bencher) {
+        struct WorldId1;
+
+        #[derive(Clone)]
+        struct Cmp1(usize);
+        #[derive(Clone)]
+        struct Cmp2(usize);
+
+        let mut world: World<WorldId1> = World::new();
+
+        world.register_component::<Cmp1>(Box::new(VecMap::new()));
+        world.register_clone::<Cmp1>();
+        world.register_component::<Cmp2>(Box::new(VecMap::new()));
+        world.register_clone::<Cmp2>();
+
+        for i in range(0usize, 10_000usize) {
+            let entity = world.create_entity();
+            world.assign_component(&entity, Cmp1(i));
+            world.assign_component(&entity, Cmp2(i));
+        }
+
+        let snapshot = world.snapshot();
+
+        bencher.iter(|| {
+            world.restore(&snapshot);
+        });
+    }
 }
\ No newline at end of file